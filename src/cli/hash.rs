@@ -0,0 +1,137 @@
+use crate::{process_hash, process_hash_check, CheckStatus, CmdExecutor, VerificationFailed};
+use anyhow::{Error, Result};
+use clap::Parser;
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+#[derive(Debug, Parser)]
+pub struct HashOpt {
+    #[arg(
+        default_value = "-",
+        help = "Files or directories to hash, - for stdin; checksum files with --check"
+    )]
+    pub paths: Vec<String>,
+
+    #[arg(short, long, default_value = "sha256")]
+    pub algorithm: HashAlgorithm,
+
+    #[arg(short, long, help = "Hash the files in directories recursively")]
+    pub recursive: bool,
+
+    #[arg(short, long, help = "Verify the files listed in checksum files")]
+    pub check: bool,
+}
+
+impl CmdExecutor for HashOpt {
+    async fn execute(self) -> Result<()> {
+        if !self.check {
+            for (digest, path) in process_hash(&self.paths, self.algorithm, self.recursive).await? {
+                println!("{}  {}", digest, path);
+            }
+            return Ok(());
+        }
+
+        let (mut failed, mut unreadable, mut malformed, mut empty) = (0, 0, 0, 0);
+        for checksum_file in &self.paths {
+            let res = process_hash_check(checksum_file, self.algorithm).await?;
+            for line in &res.malformed {
+                eprintln!(
+                    "{}: {}: improperly formatted checksum line",
+                    checksum_file, line
+                );
+            }
+            malformed += res.malformed.len();
+            if res.entries.is_empty() && !res.malformed.is_empty() {
+                eprintln!(
+                    "{}: no properly formatted checksum lines found",
+                    checksum_file
+                );
+                empty += 1;
+            }
+            for (path, status) in res.entries {
+                match status {
+                    CheckStatus::Ok => println!("{}: OK", path),
+                    CheckStatus::Failed => {
+                        failed += 1;
+                        println!("{}: FAILED", path);
+                    }
+                    CheckStatus::Unreadable => {
+                        unreadable += 1;
+                        println!("{}: FAILED open or read", path);
+                    }
+                }
+            }
+        }
+        if malformed > 0 {
+            eprintln!("WARNING: {} line(s) improperly formatted", malformed);
+        }
+        if unreadable > 0 {
+            eprintln!("WARNING: {} listed file(s) could not be read", unreadable);
+        }
+        if failed > 0 {
+            eprintln!("WARNING: {} computed checksum(s) did NOT match", failed);
+        }
+        if failed + unreadable + empty > 0 {
+            return Err(VerificationFailed.into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Blake3,
+    Sha256,
+    Sha384,
+    Sha512,
+    Sha3_256,
+    Sha3_384,
+    Sha3_512,
+    Xxh64,
+    Xxh3,
+    Xxh3_128,
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "sha384" => Ok(HashAlgorithm::Sha384),
+            "sha512" => Ok(HashAlgorithm::Sha512),
+            "sha3-256" => Ok(HashAlgorithm::Sha3_256),
+            "sha3-384" => Ok(HashAlgorithm::Sha3_384),
+            "sha3-512" => Ok(HashAlgorithm::Sha3_512),
+            "xxh64" => Ok(HashAlgorithm::Xxh64),
+            "xxh3" => Ok(HashAlgorithm::Xxh3),
+            "xxh3-128" => Ok(HashAlgorithm::Xxh3_128),
+            _ => Err(anyhow::anyhow!("Invalid hash algorithm")),
+        }
+    }
+}
+
+impl From<HashAlgorithm> for &'static str {
+    fn from(value: HashAlgorithm) -> Self {
+        match value {
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha384 => "sha384",
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Sha3_256 => "sha3-256",
+            HashAlgorithm::Sha3_384 => "sha3-384",
+            HashAlgorithm::Sha3_512 => "sha3-512",
+            HashAlgorithm::Xxh64 => "xxh64",
+            HashAlgorithm::Xxh3 => "xxh3",
+            HashAlgorithm::Xxh3_128 => "xxh3-128",
+        }
+    }
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
@@ -1,9 +1,20 @@
-use crate::{get_reader, parse_file, process_jwt_sign, process_jwt_verify, CmdExecutor};
-use anyhow::Result;
-use clap::Parser;
+use crate::{
+    format_relative, format_timestamp, get_reader, get_writer, parse_claim, parse_duration,
+    parse_exp, parse_file, parse_out, process_jwe_decrypt, process_jwe_encrypt, process_jwks,
+    process_jwt_decode, process_jwt_sign, process_jwt_verify, process_oidc_serve, read_claims_file,
+    Claims, CmdExecutor, JwtKey, JwtSecret, JwtValidation, OidcConfig, TimeStatus, TokenOpt,
+    VerificationFailed,
+};
+use anyhow::{Error, Result};
+use clap::{Args, Parser};
 use enum_dispatch::enum_dispatch;
-use regex::Regex;
-use std::time::SystemTime;
+use jsonwebtoken::Algorithm;
+use serde_json::Value;
+use std::{
+    fmt::{self, Display},
+    io::Write,
+    str::FromStr,
+};
 
 #[derive(Debug, Parser)]
 #[enum_dispatch(CmdExecutor)]
@@ -13,23 +24,162 @@ pub enum JwtSubcmd {
 
     #[command(about = "verify a token")]
     Verify(JwtVerifyOpt),
+
+    #[command(about = "decode a token without verifying it")]
+    Decode(JwtDecodeOpt),
+
+    #[command(about = "export public keys as a JWK Set")]
+    Jwks(JwtJwksOpt),
+
+    #[command(about = "encrypt a payload into a JWE")]
+    Encrypt(JwtEncryptOpt),
+
+    #[command(about = "decrypt a JWE")]
+    Decrypt(JwtDecryptOpt),
+
+    #[command(about = "run a mock OAuth2/OIDC token server")]
+    Serve(JwtServeOpt),
 }
 
+const DEFAULT_SUB: &str = "acme";
+const DEFAULT_AUD: &str = "device1";
+
 #[derive(Debug, Parser)]
 pub struct JwtSignOpt {
-    #[arg(short, long, default_value = "acme")]
-    sub: String,
+    #[arg(short, long, help = "Subject [default: acme]")]
+    sub: Option<String>,
 
-    #[arg(short, long, default_value = "device1")]
-    aud: String,
+    #[arg(short, long, help = "Audience [default: device1]")]
+    aud: Option<String>,
 
-    #[arg(short, long, value_parser=parse_exp, default_value = "1d")]
+    #[arg(
+        short,
+        long,
+        value_parser = parse_exp,
+        default_value = "1d",
+        allow_hyphen_values = true,
+        help = "Expiry: a duration from now such as 1h30m, 2w or P1DT2H, -1h for the past, or an RFC 3339 timestamp"
+    )]
     exp: u64,
+
+    #[arg(
+        long,
+        conflicts_with = "exp",
+        help = "Issue a token that never expires"
+    )]
+    no_exp: bool,
+
+    #[arg(long, help = "Issuer")]
+    iss: Option<String>,
+
+    #[arg(
+        long,
+        value_parser = parse_exp,
+        allow_hyphen_values = true,
+        help = "Not valid before: a duration from now or an RFC 3339 timestamp, like --exp"
+    )]
+    nbf: Option<u64>,
+
+    #[arg(
+        long,
+        value_parser = parse_exp,
+        allow_hyphen_values = true,
+        help = "Issued at: a duration from now such as -1h or an RFC 3339 timestamp, like --exp [default: now]"
+    )]
+    iat: Option<u64>,
+
+    #[arg(long, help = "Token id [default: random]")]
+    jti: Option<String>,
+
+    #[arg(
+        long = "claim",
+        value_name = "KEY=VALUE",
+        value_parser = parse_claim,
+        help = "Custom claim as key=value, the value parsed as JSON if it is valid JSON"
+    )]
+    claims: Vec<(String, Value)>,
+
+    #[arg(long, value_parser = parse_file, help = "JSON object of custom claims")]
+    claims_file: Option<String>,
+
+    #[arg(long, default_value = "HS256")]
+    alg: JwtAlg,
+
+    #[arg(
+        long,
+        help = "Key id for the header [default: the key file's JWK thumbprint]"
+    )]
+    kid: Option<String>,
+
+    #[command(flatten)]
+    key: JwtKeyOpt,
+}
+
+/// Where to take the key from: an HMAC secret for the HS algorithms or a key
+/// file for the others. Exactly one source is required.
+#[derive(Debug, Args)]
+#[group(required = true, multiple = false)]
+pub struct JwtKeyOpt {
+    #[arg(
+        long,
+        help = "HMAC secret (visible to other local users, prefer a file or env)"
+    )]
+    secret: Option<JwtSecret>,
+
+    #[arg(long, value_parser = parse_file, help = "Read the HMAC secret from a file")]
+    secret_file: Option<String>,
+
+    #[arg(long, help = "Read the HMAC secret from this environment variable")]
+    secret_env: Option<String>,
+
+    #[arg(
+        long,
+        value_parser = parse_file,
+        help = "PEM or DER key file: private to sign, public to verify"
+    )]
+    key: Option<String>,
+}
+
+impl JwtKeyOpt {
+    fn load(&self) -> Result<JwtKey> {
+        if let Some(secret) = &self.secret {
+            Ok(JwtKey::Secret(secret.clone()))
+        } else if let Some(file) = &self.secret_file {
+            Ok(JwtKey::Secret(JwtSecret::from_file(file)?))
+        } else if let Some(name) = &self.secret_env {
+            Ok(JwtKey::Secret(JwtSecret::from_env(name)?))
+        } else if let Some(path) = &self.key {
+            Ok(JwtKey::File(path.clone()))
+        } else {
+            Err(anyhow::anyhow!(
+                "One of --secret, --secret-file, --secret-env or --key is required"
+            ))
+        }
+    }
 }
 
 impl CmdExecutor for JwtSignOpt {
     async fn execute(self) -> Result<()> {
-        let res = process_jwt_sign(&self.sub, &self.aud, self.exp).await?;
+        let key = self.key.load()?;
+        let mut custom = match &self.claims_file {
+            Some(path) => read_claims_file(path)?,
+            None => Default::default(),
+        };
+        custom.extend(self.claims);
+        for (name, default) in [("sub", DEFAULT_SUB), ("aud", DEFAULT_AUD)] {
+            custom.entry(name).or_insert_with(|| default.into());
+        }
+        let claims = Claims {
+            sub: self.sub,
+            aud: self.aud,
+            iss: self.iss,
+            exp: (!self.no_exp).then_some(self.exp),
+            nbf: self.nbf,
+            iat: self.iat,
+            jti: self.jti,
+            custom,
+        };
+        let res = process_jwt_sign(claims, self.alg, &key, self.kid).await?;
         println!("{}", res);
         Ok(())
     }
@@ -37,38 +187,463 @@ impl CmdExecutor for JwtSignOpt {
 
 #[derive(Debug, Parser)]
 pub struct JwtVerifyOpt {
-    #[arg(short, long,value_parser = parse_file, default_value = "-")]
-    token: String,
+    #[command(flatten)]
+    token: TokenOpt,
+
+    #[arg(
+        long,
+        help = "Print a JSON report with the algorithm and key fingerprint"
+    )]
+    json: bool,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Accepted algorithms, all taking the same kind of key unless --jwks is given \
+                [default: HS256, or the algorithm of the JWK with --jwks]"
+    )]
+    alg: Vec<JwtAlg>,
+
+    #[arg(long, help = "Accepted audience, repeat to accept any of several")]
+    aud: Vec<String>,
+
+    #[arg(long, help = "Accepted issuer, repeat to accept any of several")]
+    iss: Vec<String>,
+
+    #[arg(long, help = "Required subject")]
+    sub: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = 60,
+        help = "Clock skew in seconds for exp and nbf"
+    )]
+    leeway: u64,
+
+    #[arg(long, value_name = "CLAIM", help = "Claim that must be present")]
+    require: Vec<String>,
+
+    #[arg(long, help = "Accept expired tokens, for debugging")]
+    ignore_exp: bool,
+
+    #[command(flatten)]
+    key: JwtKeyOpt,
+
+    #[arg(
+        long,
+        group = "JwtKeyOpt",
+        value_parser = parse_file,
+        help = "JWK Set to pick the key from by the token's kid"
+    )]
+    jwks: Option<String>,
 }
 
 impl CmdExecutor for JwtVerifyOpt {
     async fn execute(self) -> Result<()> {
-        let mut reader = get_reader(&self.token)?;
-        let res = process_jwt_verify(&mut reader).await?;
-        println!("{}", res);
+        let token = self.token.read()?;
+        let key = match self.jwks {
+            Some(path) => JwtKey::Jwks(path),
+            None => self.key.load()?,
+        };
+        let validation = JwtValidation {
+            algs: self.alg,
+            aud: self.aud,
+            iss: self.iss,
+            sub: self.sub,
+            leeway: self.leeway,
+            require: self.require,
+            ignore_exp: self.ignore_exp,
+        };
+        let report = process_jwt_verify(&token, &validation, &key).await?;
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else if let Some(claims) = &report.claims {
+            println!("{}", serde_json::to_string_pretty(claims)?);
+        } else if let Some(error) = &report.error {
+            eprintln!("Invalid token: {}", error);
+        }
+        if !report.valid {
+            return Err(VerificationFailed.into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct JwtDecodeOpt {
+    #[command(flatten)]
+    token: TokenOpt,
+}
+
+impl CmdExecutor for JwtDecodeOpt {
+    async fn execute(self) -> Result<()> {
+        let decoded = process_jwt_decode(&self.token.read()?)?;
+        println!(
+            "Header:\n{}",
+            serde_json::to_string_pretty(&decoded.header)?
+        );
+        println!(
+            "Payload:\n{}",
+            serde_json::to_string_pretty(&decoded.payload)?
+        );
+        let now = jsonwebtoken::get_current_timestamp() as i64;
+        for (name, timestamp) in decoded.times() {
+            println!(
+                "{}: {} ({})",
+                name,
+                format_timestamp(timestamp)?,
+                format_relative(timestamp, now)
+            );
+        }
+        match decoded.status(now) {
+            TimeStatus::Valid => {}
+            TimeStatus::Expired => println!("Status: expired"),
+            TimeStatus::NotYetValid => println!("Status: not yet valid"),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct JwtJwksOpt {
+    #[arg(
+        required = true,
+        value_parser = parse_file,
+        help = "RSA, EC or Ed25519 key files, public or private"
+    )]
+    keys: Vec<String>,
+}
+
+impl CmdExecutor for JwtJwksOpt {
+    async fn execute(self) -> Result<()> {
+        let jwks = process_jwks(&self.keys).await?;
+        println!("{}", serde_json::to_string_pretty(&jwks)?);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct JwtEncryptOpt {
+    #[arg(short, long, value_parser = parse_file, default_value = "-")]
+    input: String,
+
+    #[arg(
+        long,
+        default_value = "dir",
+        help = "Key management: dir, A256KW or ECDH-ES"
+    )]
+    alg: JweAlg,
+
+    #[arg(
+        long,
+        default_value = "A256GCM",
+        help = "Content encryption: A256GCM or C20P"
+    )]
+    enc: JweEnc,
+
+    #[arg(
+        short,
+        long,
+        value_parser = parse_file,
+        help = "32-byte key for dir and A256KW, recipient P-256 key for ECDH-ES"
+    )]
+    key: String,
+
+    #[arg(long, help = "Content type header, such as JWT for a nested token")]
+    cty: Option<String>,
+}
+
+impl CmdExecutor for JwtEncryptOpt {
+    async fn execute(self) -> Result<()> {
+        let mut reader = get_reader(&self.input)?;
+        let token =
+            process_jwe_encrypt(&mut reader, self.alg, self.enc, &self.key, self.cty).await?;
+        println!("{}", token);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct JwtDecryptOpt {
+    #[command(flatten)]
+    token: TokenOpt,
+
+    #[arg(
+        short,
+        long,
+        value_parser = parse_file,
+        help = "32-byte key for dir and A256KW, P-256 private key for ECDH-ES"
+    )]
+    key: String,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "dir",
+        help = "Accepted key management algorithms"
+    )]
+    alg: Vec<JweAlg>,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "A256GCM",
+        help = "Accepted content encryption algorithms"
+    )]
+    enc: Vec<JweEnc>,
+
+    #[arg(short, long, value_parser = parse_out, default_value = "-")]
+    output: String,
+}
+
+impl CmdExecutor for JwtDecryptOpt {
+    async fn execute(self) -> Result<()> {
+        let plaintext =
+            process_jwe_decrypt(&self.token.read()?, &self.key, &self.alg, &self.enc).await?;
+        let mut writer = get_writer(&self.output)?;
+        writer.write_all(&plaintext)?;
+        writer.flush()?;
         Ok(())
     }
 }
 
-fn parse_exp(exp: &str) -> Result<u64> {
-    let now = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)?
-        .as_secs();
-    let added = parse_exp_string(exp.to_string())?;
-    Ok(now + added)
-}
-
-fn parse_exp_string(exp: String) -> Result<u64> {
-    let re = Regex::new(r"^(?<num>\d+)(?i)(?<unit>s|m|h|d)$")?;
-    let res = re.captures(&exp).unwrap();
-    let num: u64 = res["num"].to_string().parse()?;
-    let unit = &res["unit"];
-    let exp = match unit {
-        "s" | "S" => num,
-        "m" | "M" => num * 60,
-        "h" | "H" => num * 3600,
-        "d" | "D" => num * 3600 * 24,
-        _ => return Err(anyhow::anyhow!("Invalid unit for exp")),
-    };
-    Ok(exp)
+#[derive(Debug, Parser)]
+pub struct JwtServeOpt {
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+
+    #[arg(short, long, default_value_t = 8080)]
+    port: u16,
+
+    #[arg(long, help = "Issuer URL [default: http://localhost:<port>]")]
+    issuer: Option<String>,
+
+    #[arg(long, default_value = "HS256")]
+    alg: JwtAlg,
+
+    #[arg(
+        long,
+        help = "Key id for the header and JWK Set [default: the key file's JWK thumbprint]"
+    )]
+    kid: Option<String>,
+
+    #[arg(
+        long,
+        value_parser = parse_duration,
+        default_value = "1h",
+        help = "Lifetime of issued tokens, such as 15m or PT1H"
+    )]
+    ttl: u64,
+
+    #[arg(
+        long,
+        help = "Audience of access tokens unless the request sets audience"
+    )]
+    aud: Option<String>,
+
+    #[arg(
+        long = "claim",
+        value_name = "KEY=VALUE",
+        value_parser = parse_claim,
+        help = "Custom claim for every token, as for jwt sign"
+    )]
+    claims: Vec<(String, Value)>,
+
+    #[arg(long, value_parser = parse_file, help = "JSON object of custom claims for every token")]
+    claims_file: Option<String>,
+
+    #[arg(
+        long = "client",
+        value_name = "ID:SECRET",
+        value_parser = parse_credentials,
+        help = "Accepted client [default: any client]"
+    )]
+    clients: Vec<(String, String)>,
+
+    #[arg(
+        long = "user",
+        value_name = "NAME:PASSWORD",
+        value_parser = parse_credentials,
+        help = "Accepted user for the password grant [default: any user]"
+    )]
+    users: Vec<(String, String)>,
+
+    #[command(flatten)]
+    key: JwtKeyOpt,
+}
+
+impl CmdExecutor for JwtServeOpt {
+    async fn execute(self) -> Result<()> {
+        let mut claims = match &self.claims_file {
+            Some(path) => read_claims_file(path)?,
+            None => Default::default(),
+        };
+        claims.extend(self.claims);
+        // one spelling of the issuer for discovery and the iss claim alike
+        let issuer = self
+            .issuer
+            .unwrap_or_else(|| format!("http://localhost:{}", self.port));
+        let config = OidcConfig {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            alg: self.alg,
+            key: self.key.load()?,
+            kid: self.kid,
+            ttl: self.ttl,
+            aud: self.aud,
+            claims,
+            clients: self.clients.into_iter().collect(),
+            users: self.users.into_iter().collect(),
+        };
+        process_oidc_serve(config, &self.host, self.port).await
+    }
+}
+
+/// JWE key management algorithms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JweAlg {
+    Dir,
+    A256Kw,
+    EcdhEs,
+}
+
+impl FromStr for JweAlg {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "DIR" => Ok(JweAlg::Dir),
+            "A256KW" => Ok(JweAlg::A256Kw),
+            "ECDH-ES" => Ok(JweAlg::EcdhEs),
+            _ => Err(anyhow::anyhow!("Invalid JWE key management algorithm")),
+        }
+    }
+}
+
+impl From<JweAlg> for &'static str {
+    fn from(value: JweAlg) -> Self {
+        match value {
+            JweAlg::Dir => "dir",
+            JweAlg::A256Kw => "A256KW",
+            JweAlg::EcdhEs => "ECDH-ES",
+        }
+    }
+}
+
+impl Display for JweAlg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+/// JWE content encryption algorithms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JweEnc {
+    A256Gcm,
+    C20P,
+}
+
+impl FromStr for JweEnc {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "A256GCM" => Ok(JweEnc::A256Gcm),
+            "C20P" => Ok(JweEnc::C20P),
+            _ => Err(anyhow::anyhow!("Invalid JWE content encryption algorithm")),
+        }
+    }
+}
+
+impl From<JweEnc> for &'static str {
+    fn from(value: JweEnc) -> Self {
+        match value {
+            JweEnc::A256Gcm => "A256GCM",
+            JweEnc::C20P => "C20P",
+        }
+    }
+}
+
+impl Display for JweEnc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JwtAlg {
+    Hs256,
+    Hs384,
+    Hs512,
+    Rs256,
+    Rs384,
+    Rs512,
+    Ps256,
+    Es256,
+    Es384,
+    EdDsa,
+}
+
+impl FromStr for JwtAlg {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "HS256" => Ok(JwtAlg::Hs256),
+            "HS384" => Ok(JwtAlg::Hs384),
+            "HS512" => Ok(JwtAlg::Hs512),
+            "RS256" => Ok(JwtAlg::Rs256),
+            "RS384" => Ok(JwtAlg::Rs384),
+            "RS512" => Ok(JwtAlg::Rs512),
+            "PS256" => Ok(JwtAlg::Ps256),
+            "ES256" => Ok(JwtAlg::Es256),
+            "ES384" => Ok(JwtAlg::Es384),
+            "EDDSA" => Ok(JwtAlg::EdDsa),
+            _ => Err(anyhow::anyhow!("Invalid JWT algorithm")),
+        }
+    }
+}
+
+impl From<JwtAlg> for &'static str {
+    fn from(value: JwtAlg) -> Self {
+        match value {
+            JwtAlg::Hs256 => "HS256",
+            JwtAlg::Hs384 => "HS384",
+            JwtAlg::Hs512 => "HS512",
+            JwtAlg::Rs256 => "RS256",
+            JwtAlg::Rs384 => "RS384",
+            JwtAlg::Rs512 => "RS512",
+            JwtAlg::Ps256 => "PS256",
+            JwtAlg::Es256 => "ES256",
+            JwtAlg::Es384 => "ES384",
+            JwtAlg::EdDsa => "EdDSA",
+        }
+    }
+}
+
+impl From<JwtAlg> for Algorithm {
+    fn from(value: JwtAlg) -> Self {
+        match value {
+            JwtAlg::Hs256 => Algorithm::HS256,
+            JwtAlg::Hs384 => Algorithm::HS384,
+            JwtAlg::Hs512 => Algorithm::HS512,
+            JwtAlg::Rs256 => Algorithm::RS256,
+            JwtAlg::Rs384 => Algorithm::RS384,
+            JwtAlg::Rs512 => Algorithm::RS512,
+            JwtAlg::Ps256 => Algorithm::PS256,
+            JwtAlg::Es256 => Algorithm::ES256,
+            JwtAlg::Es384 => Algorithm::ES384,
+            JwtAlg::EdDsa => Algorithm::EdDSA,
+        }
+    }
+}
+
+impl Display for JwtAlg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+/// Parse `name:secret`; the secret may itself contain colons.
+fn parse_credentials(credentials: &str) -> Result<(String, String)> {
+    match credentials.split_once(':') {
+        Some((name, secret)) if !name.is_empty() => Ok((name.into(), secret.into())),
+        _ => Err(anyhow::anyhow!("Expected name:secret")),
+    }
 }
@@ -1,18 +1,24 @@
 mod base;
 mod csv;
 mod genpass;
+mod hash;
 mod http;
 mod jwt;
+mod paseto;
 mod text;
+mod token;
 
 pub use self::genpass::GenPwdOpt;
 pub use base::*;
 use clap::{Parser, Subcommand};
 pub use csv::{CsvOpt, Ofmt};
 use enum_dispatch::enum_dispatch;
+pub use hash::*;
 pub use http::*;
 pub use jwt::*;
+pub use paseto::*;
 pub use text::*;
+pub use token::*;
 
 #[derive(Debug, Parser)]
 #[command(name="rcli", version, about, long_about = None)]
@@ -30,6 +36,12 @@ pub enum SubCmd {
     #[command(name = "genpass", about = "Gen a password")]
     Genpassword(GenPwdOpt),
 
+    #[command(
+        name = "hash",
+        about = "Hash files, directories or stdin, or verify checksum files"
+    )]
+    Hash(HashOpt),
+
     #[command(subcommand)]
     Base64(BaseSubcmd),
 
@@ -41,4 +53,7 @@ pub enum SubCmd {
 
     #[command(subcommand)]
     Jwt(JwtSubcmd),
+
+    #[command(subcommand)]
+    Paseto(PasetoSubcmd),
 }
@@ -0,0 +1,293 @@
+use crate::{
+    format_relative, format_timestamp, parse_claim, parse_exp, parse_file, process_paseto_decode,
+    process_paseto_sign, process_paseto_verify, read_claims_file, Claims, CmdExecutor,
+    PasetoValidation, TimeStatus, TokenOpt, VerificationFailed,
+};
+use anyhow::{Error, Result};
+use clap::Parser;
+use enum_dispatch::enum_dispatch;
+use serde_json::Value;
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+#[derive(Debug, Parser)]
+#[enum_dispatch(CmdExecutor)]
+pub enum PasetoSubcmd {
+    #[command(about = "sign or encrypt a v4 token")]
+    Sign(PasetoSignOpt),
+
+    #[command(about = "verify a v4 token")]
+    Verify(PasetoVerifyOpt),
+
+    #[command(about = "decode a token without verifying it")]
+    Decode(PasetoDecodeOpt),
+}
+
+#[derive(Debug, Parser)]
+pub struct PasetoSignOpt {
+    #[arg(
+        long,
+        default_value = "public",
+        help = "public signs with an Ed25519 key, local encrypts with a 32-byte key"
+    )]
+    purpose: PasetoPurpose,
+
+    #[arg(
+        short,
+        long,
+        value_parser = parse_file,
+        help = "Ed25519 private key for public, 32-byte key for local"
+    )]
+    key: String,
+
+    #[arg(short, long, help = "Subject")]
+    sub: Option<String>,
+
+    #[arg(short, long, help = "Audience")]
+    aud: Option<String>,
+
+    #[arg(long, help = "Issuer")]
+    iss: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        value_parser = parse_exp,
+        default_value = "1h",
+        allow_hyphen_values = true,
+        help = "Expiry: a duration from now such as 1h30m or P1D, or an RFC 3339 timestamp"
+    )]
+    exp: u64,
+
+    #[arg(
+        long,
+        conflicts_with = "exp",
+        help = "Issue a token that never expires"
+    )]
+    no_exp: bool,
+
+    #[arg(
+        long,
+        value_parser = parse_exp,
+        allow_hyphen_values = true,
+        help = "Not valid before: a duration from now or an RFC 3339 timestamp, like --exp"
+    )]
+    nbf: Option<u64>,
+
+    #[arg(
+        long,
+        value_parser = parse_exp,
+        allow_hyphen_values = true,
+        help = "Issued at: a duration from now such as -1h or an RFC 3339 timestamp, like --exp [default: now]"
+    )]
+    iat: Option<u64>,
+
+    #[arg(long, help = "Token id [default: random]")]
+    jti: Option<String>,
+
+    #[arg(
+        long = "claim",
+        value_name = "KEY=VALUE",
+        value_parser = parse_claim,
+        help = "Custom claim as key=value, the value parsed as JSON if it is valid JSON"
+    )]
+    claims: Vec<(String, Value)>,
+
+    #[arg(long, value_parser = parse_file, help = "JSON object of custom claims")]
+    claims_file: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "",
+        help = "Footer, authenticated but not encrypted"
+    )]
+    footer: String,
+
+    #[arg(
+        long,
+        default_value = "",
+        help = "Implicit assertion, authenticated but not part of the token"
+    )]
+    implicit: String,
+}
+
+impl CmdExecutor for PasetoSignOpt {
+    async fn execute(self) -> Result<()> {
+        let mut custom = match &self.claims_file {
+            Some(path) => read_claims_file(path)?,
+            None => Default::default(),
+        };
+        custom.extend(self.claims);
+        let claims = Claims {
+            sub: self.sub,
+            aud: self.aud,
+            iss: self.iss,
+            exp: (!self.no_exp).then_some(self.exp),
+            nbf: self.nbf,
+            iat: self.iat,
+            jti: self.jti,
+            custom,
+        };
+        let token = process_paseto_sign(
+            claims,
+            self.purpose,
+            &self.key,
+            &self.footer,
+            &self.implicit,
+        )
+        .await?;
+        println!("{}", token);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct PasetoVerifyOpt {
+    #[command(flatten)]
+    token: TokenOpt,
+
+    #[arg(
+        long,
+        help = "Print a JSON report with the purpose and key fingerprint"
+    )]
+    json: bool,
+
+    #[arg(long, default_value = "public", help = "Purpose the token must have")]
+    purpose: PasetoPurpose,
+
+    #[arg(
+        short,
+        long,
+        value_parser = parse_file,
+        help = "Ed25519 public key for public, 32-byte key for local"
+    )]
+    key: String,
+
+    #[arg(long, help = "Expected audience")]
+    aud: Option<String>,
+
+    #[arg(long, help = "Expected issuer")]
+    iss: Option<String>,
+
+    #[arg(long, help = "Expected subject")]
+    sub: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = 60,
+        help = "Seconds of clock skew tolerated for exp and nbf"
+    )]
+    leeway: u64,
+
+    #[arg(long, help = "Accept expired tokens and tokens without exp")]
+    ignore_exp: bool,
+
+    #[arg(long, help = "Footer the token must carry [default: any]")]
+    footer: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "",
+        help = "Implicit assertion the token was made with"
+    )]
+    implicit: String,
+}
+
+impl CmdExecutor for PasetoVerifyOpt {
+    async fn execute(self) -> Result<()> {
+        let token = self.token.read()?;
+        let validation = PasetoValidation {
+            aud: self.aud,
+            iss: self.iss,
+            sub: self.sub,
+            leeway: self.leeway,
+            ignore_exp: self.ignore_exp,
+            footer: self.footer,
+            implicit: self.implicit,
+        };
+        let report = process_paseto_verify(&token, self.purpose, &self.key, &validation).await?;
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else if let Some(claims) = &report.claims {
+            println!("{}", serde_json::to_string_pretty(claims)?);
+        } else if let Some(error) = &report.error {
+            eprintln!("Invalid token: {}", error);
+        }
+        if !report.valid {
+            return Err(VerificationFailed.into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct PasetoDecodeOpt {
+    #[command(flatten)]
+    token: TokenOpt,
+}
+
+impl CmdExecutor for PasetoDecodeOpt {
+    async fn execute(self) -> Result<()> {
+        let decoded = process_paseto_decode(&self.token.read()?)?;
+        println!("Header: v4.{}", decoded.purpose);
+        match &decoded.payload {
+            Some(payload) => println!("Payload:\n{}", serde_json::to_string_pretty(payload)?),
+            None => println!("Payload: encrypted"),
+        }
+        if !decoded.footer.is_empty() {
+            println!("Footer: {}", decoded.footer);
+        }
+        let now = jsonwebtoken::get_current_timestamp() as i64;
+        for (name, timestamp) in decoded.times() {
+            println!(
+                "{}: {} ({})",
+                name,
+                format_timestamp(timestamp)?,
+                format_relative(timestamp, now)
+            );
+        }
+        match decoded.status(now) {
+            TimeStatus::Valid => {}
+            TimeStatus::Expired => println!("Status: expired"),
+            TimeStatus::NotYetValid => println!("Status: not yet valid"),
+        }
+        Ok(())
+    }
+}
+
+/// PASETO purposes: `local` for symmetric encryption, `public` for signatures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasetoPurpose {
+    Local,
+    Public,
+}
+
+impl FromStr for PasetoPurpose {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "local" => Ok(PasetoPurpose::Local),
+            "public" => Ok(PasetoPurpose::Public),
+            _ => Err(anyhow::anyhow!(
+                "Invalid PASETO purpose, expected local or public"
+            )),
+        }
+    }
+}
+
+impl From<PasetoPurpose> for &'static str {
+    fn from(value: PasetoPurpose) -> Self {
+        match value {
+            PasetoPurpose::Local => "local",
+            PasetoPurpose::Public => "public",
+        }
+    }
+}
+
+impl Display for PasetoPurpose {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
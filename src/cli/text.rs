@@ -1,13 +1,20 @@
 use crate::{
-    parse_file, parse_out, process_gen_key, process_sign, process_text_decrypt,
-    process_text_encrypt, process_verify, CmdExecutor,
+    get_writer, parse_file, parse_out, process_age_encrypt, process_convert_key,
+    process_gen_identity, process_gen_key, process_sign, process_sign_manifest,
+    process_stream_encrypt, process_text_decrypt, process_text_encrypt, process_verify_manifest,
+    process_verify_report, read_passphrase, CmdExecutor, VerificationFailed,
 };
 use anyhow::{Error, Result};
-use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
-use clap::{Parser, Subcommand};
+use base64::{
+    engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD},
+    Engine,
+};
+use clap::{ArgGroup, Parser, Subcommand};
 use enum_dispatch::enum_dispatch;
 use std::{
     fmt::{self, Display},
+    fs,
+    io::Write,
     str::FromStr,
 };
 
@@ -20,47 +27,196 @@ pub enum TextSubcmd {
     #[command(about = "Verify a signature with a public/session key")]
     Verify(VerifyOpt),
 
-    #[command(about = "Generate a key for blake3/ed25519")]
+    #[command(about = "Sign every file in a directory and put out a manifest")]
+    SignManifest(SignManifestOpt),
+
+    #[command(about = "Verify the files in a directory against a signed manifest")]
+    VerifyManifest(VerifyManifestOpt),
+
+    #[command(about = "Generate a blake3 key or an ed25519 keypair (<output> and <output>.pub)")]
     Genkey(GenKeyOpt),
 
-    #[command(about = "Encrypt a text and put out base64")]
+    #[command(about = "Generate an age X25519 identity and print its recipient")]
+    GenIdentity(GenIdentityOpt),
+
+    #[command(about = "Convert an ed25519 key between raw, PEM, OpenSSH and JWK formats")]
+    ConvertKey(ConvertKeyOpt),
+
+    #[command(
+        about = "Encrypt a text into an armored message, or a binary file with --stream; \
+                 age format with --age or recipients, binary with --binary"
+    )]
     Encrypt(EncryptOpt),
 
-    #[command(about = "Encrypt a base64 text and put out raw text")]
+    #[command(about = "Decrypt an rcli or age message, armored or binary")]
     Decrypt(DecryptOpt),
 }
 
 #[derive(Debug, Parser)]
+#[command(group(ArgGroup::new("age_mode").args(["age", "recipient", "recipients_file"]).multiple(true)))]
 pub struct EncryptOpt {
-    #[arg(short, long, value_parser=parse_file)]
-    pub key: String,
+    #[arg(
+        short,
+        long,
+        value_parser=parse_file,
+        required_unless_present_any = ["password", "recipient", "recipients_file", "age"]
+    )]
+    pub key: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["key", "age_mode"],
+        help = "Derive the key from a password instead of a key file"
+    )]
+    pub password: bool,
+
+    #[arg(
+        short,
+        long,
+        conflicts_with_all = ["key", "password"],
+        help = "Encrypt to an age X25519 recipient (age1...), may be repeated"
+    )]
+    pub recipient: Vec<String>,
+
+    #[arg(
+        short = 'R',
+        long,
+        value_parser = parse_file,
+        conflicts_with_all = ["key", "password"],
+        help = "Encrypt to the recipients listed in a file, may be repeated"
+    )]
+    pub recipients_file: Vec<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "key",
+        help = "Write an age file; without recipients it is protected by a passphrase"
+    )]
+    pub age: bool,
 
     #[arg(short, long, value_parser=parse_file, default_value="-")]
     pub input: String,
+
+    #[arg(short, long, value_parser = parse_out, default_value = "-")]
+    pub output: String,
+
+    #[arg(
+        long,
+        conflicts_with = "age_mode",
+        help = "Use chunked binary encryption for files of any size"
+    )]
+    pub stream: bool,
+
+    #[arg(
+        long,
+        requires = "age_mode",
+        help = "Write a binary age file instead of an armored one"
+    )]
+    pub binary: bool,
+
+    #[arg(long, default_value = "chacha20-poly1305", conflicts_with = "age_mode")]
+    pub cipher: Cipher,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["recipient", "recipients_file", "age"],
+        help = "Associated data binding the message to a context, or @<file> to read it from a file"
+    )]
+    pub aad: Option<String>,
+
+    #[arg(long, value_parser = parse_file, help = "Read the key passphrase or password from a file")]
+    pub passphrase_file: Option<String>,
 }
 
 impl CmdExecutor for EncryptOpt {
     async fn execute(self) -> Result<()> {
-        let res = process_text_encrypt(&self.key, &self.input).await?;
-        print!("{}", res);
+        let passphrase_file = self.passphrase_file.as_deref();
+        let aad = self.aad.as_deref().map(read_aad).transpose()?;
+        if self.age || !self.recipient.is_empty() || !self.recipients_file.is_empty() {
+            return process_age_encrypt(
+                &self.input,
+                &self.output,
+                &self.recipient,
+                &self.recipients_file,
+                self.binary,
+                passphrase_file,
+            )
+            .await;
+        }
+        if self.stream {
+            return process_stream_encrypt(
+                self.key.as_deref(),
+                &self.input,
+                &self.output,
+                self.cipher,
+                aad.as_deref(),
+                passphrase_file,
+            )
+            .await;
+        }
+        let res = process_text_encrypt(
+            self.key.as_deref(),
+            &self.input,
+            self.cipher,
+            aad.as_deref(),
+            passphrase_file,
+        )
+        .await?;
+        get_writer(&self.output)?.write_all(res.as_bytes())?;
         Ok(())
     }
 }
 
 #[derive(Debug, Parser)]
 pub struct DecryptOpt {
-    #[arg(short, long, value_parser=parse_file)]
-    pub key: String,
+    #[arg(
+        short,
+        long,
+        value_parser=parse_file,
+        help = "Key file, not needed for password protected messages"
+    )]
+    pub key: Option<String>,
+
+    #[arg(long, value_parser = parse_file, help = "age identity file, may be repeated")]
+    pub identity: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Associated data binding the message to a context, or @<file> to read it from a file"
+    )]
+    pub aad: Option<String>,
 
     #[arg(short, long, value_parser=parse_file, default_value="-")]
     pub input: String,
+
+    #[arg(short, long, value_parser = parse_out, default_value = "-")]
+    pub output: String,
+
+    #[arg(long, value_parser = parse_file, help = "Read the key passphrase or password from a file")]
+    pub passphrase_file: Option<String>,
 }
 
 impl CmdExecutor for DecryptOpt {
     async fn execute(self) -> Result<()> {
-        let res = process_text_decrypt(&self.key, &self.input).await?;
-        print!("{}", res);
-        Ok(())
+        let aad = self.aad.as_deref().map(read_aad).transpose()?;
+        process_text_decrypt(
+            self.key.as_deref(),
+            &self.identity,
+            &self.input,
+            &self.output,
+            aad.as_deref(),
+            self.passphrase_file.as_deref(),
+        )
+        .await
+    }
+}
+
+/// Associated data given on the command line: the value itself, or the
+/// contents of a file when it starts with `@`.
+fn read_aad(value: &str) -> Result<Vec<u8>> {
+    match value.strip_prefix('@') {
+        Some(path) => Ok(fs::read(path)?),
+        None => Ok(value.as_bytes().to_vec()),
     }
 }
 
@@ -68,11 +224,79 @@ impl CmdExecutor for DecryptOpt {
 pub struct GenKeyOpt {
     #[arg(short, long, value_parser = parse_out, default_value = "-")]
     pub output: String,
+
+    #[arg(short, long, default_value = "blake3")]
+    pub format: SignFormat,
+
+    #[arg(short, long, default_value = "hex")]
+    pub encoding: KeyEncoding,
+
+    #[arg(long, help = "Protect the secret key with a passphrase")]
+    pub passphrase: bool,
+
+    #[arg(long, value_parser = parse_file, requires = "passphrase")]
+    pub passphrase_file: Option<String>,
 }
 
 impl CmdExecutor for GenKeyOpt {
     async fn execute(self) -> Result<()> {
-        process_gen_key(&self.output).await
+        let passphrase = if self.passphrase {
+            Some(read_passphrase(self.passphrase_file.as_deref(), true)?)
+        } else {
+            None
+        };
+        process_gen_key(
+            &self.output,
+            self.format,
+            self.encoding,
+            passphrase.as_deref().map(|p| p.as_str()),
+        )
+        .await
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct GenIdentityOpt {
+    #[arg(short, long, value_parser = parse_out, default_value = "-")]
+    pub output: String,
+}
+
+impl CmdExecutor for GenIdentityOpt {
+    async fn execute(self) -> Result<()> {
+        let recipient = process_gen_identity(&self.output).await?;
+        eprintln!("Public key: {}", recipient);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct ConvertKeyOpt {
+    #[arg(short, long, value_parser = parse_file, default_value = "-")]
+    pub input: String,
+
+    #[arg(short, long, value_parser = parse_out, default_value = "-")]
+    pub output: String,
+
+    #[arg(short, long, default_value = "pem")]
+    pub format: KeyFormat,
+
+    #[arg(long, help = "Write only the public key")]
+    pub public: bool,
+
+    #[arg(long, help = "Read a raw, hex or base64 input as a public key")]
+    pub input_public: bool,
+}
+
+impl CmdExecutor for ConvertKeyOpt {
+    async fn execute(self) -> Result<()> {
+        process_convert_key(
+            &self.input,
+            &self.output,
+            self.format,
+            self.public,
+            self.input_public,
+        )
+        .await
     }
 }
 
@@ -86,13 +310,35 @@ pub struct SignOpt {
 
     #[arg(short, long, default_value = "blake3")]
     pub format: SignFormat,
+
+    #[arg(short, long, default_value = "base64", help = "Signature encoding")]
+    pub encoding: SigEncoding,
+
+    #[arg(
+        long,
+        value_parser = parse_out,
+        help = "Write the signature to a detached .sig file"
+    )]
+    pub sig_out: Option<String>,
+
+    #[arg(long, value_parser = parse_file, help = "Read the key passphrase from a file")]
+    pub passphrase_file: Option<String>,
 }
 
 impl CmdExecutor for SignOpt {
     async fn execute(self) -> Result<()> {
-        let res = process_sign(&self.input, &self.key, self.format).await?;
-        let encoded = URL_SAFE_NO_PAD.encode(res);
-        println!("{}", encoded);
+        let res = process_sign(
+            &self.input,
+            &self.key,
+            self.format,
+            self.passphrase_file.as_deref(),
+        )
+        .await?;
+        let encoded = self.encoding.encode(&res);
+        match self.sig_out {
+            Some(path) => fs::write(path, encoded + "\n")?,
+            None => println!("{}", encoded),
+        }
         Ok(())
     }
 }
@@ -108,15 +354,143 @@ pub struct VerifyOpt {
     #[arg(short, long, default_value = "blake3")]
     pub format: SignFormat,
 
-    #[arg(short, long)]
-    pub sig: String,
+    #[arg(short, long, default_value = "base64", help = "Signature encoding")]
+    pub encoding: SigEncoding,
+
+    #[arg(short, long, required_unless_present = "sig_file")]
+    pub sig: Option<String>,
+
+    #[arg(
+        long,
+        value_parser = parse_file,
+        conflicts_with = "sig",
+        help = "Read the signature from a detached .sig file"
+    )]
+    pub sig_file: Option<String>,
+
+    #[arg(
+        long,
+        help = "Print a JSON report with the algorithm and key fingerprint"
+    )]
+    pub json: bool,
+
+    #[arg(long, value_parser = parse_file, help = "Read the key passphrase from a file")]
+    pub passphrase_file: Option<String>,
 }
 
 impl CmdExecutor for VerifyOpt {
     async fn execute(self) -> Result<()> {
-        let sign = URL_SAFE_NO_PAD.decode(self.sig)?;
-        let res = process_verify(&self.input, &self.key, self.format, &sign).await?;
-        println!("{}", res);
+        let sig = match (self.sig, self.sig_file) {
+            (Some(sig), _) => sig,
+            (None, Some(path)) => fs::read_to_string(path)?,
+            (None, None) => return Err(anyhow::anyhow!("A signature is required")),
+        };
+        // a signature that does not decode is invalid, not an error
+        let sign = self.encoding.decode(&sig).unwrap_or_default();
+        let report = process_verify_report(
+            &self.input,
+            &self.key,
+            self.format,
+            &sign,
+            self.passphrase_file.as_deref(),
+        )
+        .await?;
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            println!("{}", report.valid);
+        }
+        if !report.valid {
+            return Err(VerificationFailed.into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct SignManifestOpt {
+    #[arg(short, long, value_parser = parse_file)]
+    pub dir: String,
+
+    #[arg(short, long)]
+    pub key: String,
+
+    #[arg(short, long, default_value = "blake3")]
+    pub format: SignFormat,
+
+    #[arg(short, long, value_parser = parse_out, default_value = "-")]
+    pub output: String,
+
+    #[arg(long, value_parser = parse_file, help = "Read the key passphrase from a file")]
+    pub passphrase_file: Option<String>,
+}
+
+impl CmdExecutor for SignManifestOpt {
+    async fn execute(self) -> Result<()> {
+        let manifest = process_sign_manifest(
+            &self.dir,
+            &self.key,
+            self.format,
+            self.passphrase_file.as_deref(),
+        )
+        .await?;
+        let mut writer = get_writer(&self.output)?;
+        serde_json::to_writer_pretty(&mut writer, &manifest)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+pub struct VerifyManifestOpt {
+    #[arg(short, long, value_parser = parse_file)]
+    pub manifest: String,
+
+    #[arg(short, long, value_parser = parse_file, default_value = ".")]
+    pub dir: String,
+
+    #[arg(short, long)]
+    pub key: String,
+
+    #[arg(short, long, help = "Format the manifest must be signed with")]
+    pub format: SignFormat,
+
+    #[arg(long, value_parser = parse_file, help = "Read the key passphrase from a file")]
+    pub passphrase_file: Option<String>,
+}
+
+impl CmdExecutor for VerifyManifestOpt {
+    async fn execute(self) -> Result<()> {
+        let res = process_verify_manifest(
+            &self.dir,
+            &self.manifest,
+            &self.key,
+            self.format,
+            self.passphrase_file.as_deref(),
+        )
+        .await?;
+        if !res.signature_valid {
+            eprintln!("WARNING: the manifest signature is invalid");
+        }
+        let failed = res.files.iter().filter(|(_, valid)| !valid).count();
+        for (path, valid) in res.files {
+            println!("{}: {}", path, if valid { "OK" } else { "FAILED" });
+        }
+        for path in &res.unlisted {
+            println!("{}: NOT IN MANIFEST", path);
+        }
+        if failed > 0 {
+            eprintln!("WARNING: {} file(s) failed verification", failed);
+        }
+        if !res.unlisted.is_empty() {
+            eprintln!(
+                "WARNING: {} file(s) not listed in the manifest",
+                res.unlisted.len()
+            );
+        }
+        if !res.signature_valid || failed > 0 || !res.unlisted.is_empty() {
+            return Err(VerificationFailed.into());
+        }
         Ok(())
     }
 }
@@ -125,6 +499,9 @@ impl CmdExecutor for VerifyOpt {
 pub enum SignFormat {
     Blake3,
     Ed25519,
+    Ed25519ph,
+    HmacSha256,
+    HmacSha512,
 }
 
 impl FromStr for SignFormat {
@@ -133,6 +510,9 @@ impl FromStr for SignFormat {
         match s {
             "blake3" => Ok(SignFormat::Blake3),
             "ed25519" => Ok(SignFormat::Ed25519),
+            "ed25519ph" => Ok(SignFormat::Ed25519ph),
+            "hmac-sha256" => Ok(SignFormat::HmacSha256),
+            "hmac-sha512" => Ok(SignFormat::HmacSha512),
             _ => Err(anyhow::anyhow!("Invalid format")),
         }
     }
@@ -143,6 +523,9 @@ impl From<SignFormat> for &'static str {
         match value {
             SignFormat::Blake3 => "blake3",
             SignFormat::Ed25519 => "ed25519",
+            SignFormat::Ed25519ph => "ed25519ph",
+            SignFormat::HmacSha256 => "hmac-sha256",
+            SignFormat::HmacSha512 => "hmac-sha512",
         }
     }
 }
@@ -152,3 +535,173 @@ impl Display for SignFormat {
         write!(f, "{}", Into::<&str>::into(*self))
     }
 }
+
+#[derive(Debug, Clone, Copy)]
+pub enum SigEncoding {
+    Base64,
+    Hex,
+}
+
+impl SigEncoding {
+    pub fn encode(self, sig: &[u8]) -> String {
+        match self {
+            SigEncoding::Base64 => URL_SAFE_NO_PAD.encode(sig),
+            SigEncoding::Hex => hex::encode(sig),
+        }
+    }
+
+    /// Decode a signature; base64 is accepted in any of its common variants.
+    pub fn decode(self, sig: &str) -> Result<Vec<u8>> {
+        let sig = sig.trim();
+        match self {
+            SigEncoding::Base64 => [STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD]
+                .iter()
+                .find_map(|engine| engine.decode(sig).ok())
+                .ok_or_else(|| anyhow::anyhow!("Invalid base64 signature")),
+            SigEncoding::Hex => Ok(hex::decode(sig)?),
+        }
+    }
+}
+
+impl FromStr for SigEncoding {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "base64" => Ok(SigEncoding::Base64),
+            "hex" => Ok(SigEncoding::Hex),
+            _ => Err(anyhow::anyhow!("Invalid signature encoding")),
+        }
+    }
+}
+
+impl From<SigEncoding> for &'static str {
+    fn from(value: SigEncoding) -> Self {
+        match value {
+            SigEncoding::Base64 => "base64",
+            SigEncoding::Hex => "hex",
+        }
+    }
+}
+
+impl Display for SigEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum KeyEncoding {
+    Raw,
+    Hex,
+    Base64,
+}
+
+impl FromStr for KeyEncoding {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(KeyEncoding::Raw),
+            "hex" => Ok(KeyEncoding::Hex),
+            "base64" => Ok(KeyEncoding::Base64),
+            _ => Err(anyhow::anyhow!("Invalid encoding")),
+        }
+    }
+}
+
+impl From<KeyEncoding> for &'static str {
+    fn from(value: KeyEncoding) -> Self {
+        match value {
+            KeyEncoding::Raw => "raw",
+            KeyEncoding::Hex => "hex",
+            KeyEncoding::Base64 => "base64",
+        }
+    }
+}
+
+impl Display for KeyEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum KeyFormat {
+    Raw,
+    Hex,
+    Base64,
+    Pem,
+    Openssh,
+    Jwk,
+}
+
+impl FromStr for KeyFormat {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(KeyFormat::Raw),
+            "hex" => Ok(KeyFormat::Hex),
+            "base64" => Ok(KeyFormat::Base64),
+            "pem" => Ok(KeyFormat::Pem),
+            "openssh" => Ok(KeyFormat::Openssh),
+            "jwk" => Ok(KeyFormat::Jwk),
+            _ => Err(anyhow::anyhow!("Invalid key format")),
+        }
+    }
+}
+
+impl From<KeyFormat> for &'static str {
+    fn from(value: KeyFormat) -> Self {
+        match value {
+            KeyFormat::Raw => "raw",
+            KeyFormat::Hex => "hex",
+            KeyFormat::Base64 => "base64",
+            KeyFormat::Pem => "pem",
+            KeyFormat::Openssh => "openssh",
+            KeyFormat::Jwk => "jwk",
+        }
+    }
+}
+
+impl Display for KeyFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    ChaCha20Poly1305,
+    XChaCha20Poly1305,
+    Aes256Gcm,
+    Aes256GcmSiv,
+}
+
+impl FromStr for Cipher {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chacha20-poly1305" => Ok(Cipher::ChaCha20Poly1305),
+            "xchacha20-poly1305" => Ok(Cipher::XChaCha20Poly1305),
+            "aes-256-gcm" => Ok(Cipher::Aes256Gcm),
+            "aes-256-gcm-siv" => Ok(Cipher::Aes256GcmSiv),
+            _ => Err(anyhow::anyhow!("Invalid cipher")),
+        }
+    }
+}
+
+impl From<Cipher> for &'static str {
+    fn from(value: Cipher) -> Self {
+        match value {
+            Cipher::ChaCha20Poly1305 => "chacha20-poly1305",
+            Cipher::XChaCha20Poly1305 => "xchacha20-poly1305",
+            Cipher::Aes256Gcm => "aes-256-gcm",
+            Cipher::Aes256GcmSiv => "aes-256-gcm-siv",
+        }
+    }
+}
+
+impl Display for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
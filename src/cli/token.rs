@@ -0,0 +1,35 @@
+use crate::{get_reader, parse_file, parse_time, read_token};
+use anyhow::Result;
+use clap::Args;
+use serde_json::Value;
+
+/// Where to read the token from, shared by the commands that take one.
+#[derive(Debug, Args)]
+pub struct TokenOpt {
+    #[arg(short, long, value_parser = parse_file, default_value = "-")]
+    token: String,
+}
+
+impl TokenOpt {
+    pub fn read(&self) -> Result<String> {
+        read_token(&mut get_reader(&self.token)?)
+    }
+}
+
+/// Parse `key=value`, taking the value as JSON when it parses and as a string
+/// otherwise, so `--claim admin=true` is a boolean and `--claim team=red` a string.
+pub fn parse_claim(claim: &str) -> Result<(String, Value)> {
+    let (key, value) = claim
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Expected key=value"))?;
+    if key.is_empty() {
+        return Err(anyhow::anyhow!("Claim name must not be empty"));
+    }
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.into()));
+    Ok((key.into(), value))
+}
+
+/// Value parser for `--exp`, `--nbf` and `--iat`, resolved against the current time.
+pub fn parse_exp(exp: &str) -> Result<u64> {
+    parse_time(exp, jsonwebtoken::get_current_timestamp())
+}
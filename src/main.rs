@@ -1,10 +1,19 @@
-use anyhow::Result;
 use clap::Parser;
-use rcli::{CmdExecutor, Rcli};
+use rcli::{CmdExecutor, Rcli, VerificationFailed};
+use std::process::ExitCode;
 
+/// Exit codes: 0 success (or a valid signature), 1 failed verification,
+/// 2 any other error.
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     tracing_subscriber::fmt::init();
     let rcli = Rcli::parse();
-    rcli.cmd.execute().await
+    match rcli.cmd.execute().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.is::<VerificationFailed>() => ExitCode::from(1),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(2)
+        }
+    }
 }
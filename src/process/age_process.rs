@@ -0,0 +1,183 @@
+use crate::{get_reader, get_writer, read_passphrase};
+use age::armor::{ArmoredReader, ArmoredWriter, Format};
+use age::secrecy::{ExposeSecret, SecretString};
+use age::{x25519, Decryptor, Encryptor, IdentityFile, IdentityFileEntry};
+use anyhow::Result;
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+
+const AGE_MAGIC: &[u8] = b"age-encryption.org/";
+const AGE_ARMOR_BEGIN: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+/// Bytes to peek at to tell an age file from other input.
+pub(crate) const AGE_PEEK_LEN: usize = AGE_ARMOR_BEGIN.len() + 8;
+
+/// Whether `head`, the start of the input, is an age file, binary or armored.
+pub(crate) fn is_age(head: &[u8]) -> bool {
+    let start = head
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(head.len());
+    head.starts_with(AGE_MAGIC) || head[start..].starts_with(AGE_ARMOR_BEGIN)
+}
+
+/// Generate an X25519 identity and write it to `output` in the age-keygen
+/// format. Returns the matching `age1...` recipient.
+pub async fn process_gen_identity(output: &str) -> Result<String> {
+    let identity = x25519::Identity::generate();
+    let recipient = identity.to_public().to_string();
+    let mut writer = get_writer(output)?;
+    writeln!(writer, "# public key: {}", recipient)?;
+    writeln!(writer, "{}", identity.to_string().expose_secret())?;
+    Ok(recipient)
+}
+
+/// Collect recipients given directly and from recipients files, where blank
+/// lines and `#` comments are skipped.
+fn parse_recipients(
+    recipients: &[String],
+    recipients_files: &[String],
+) -> Result<Vec<Box<dyn age::Recipient + Send>>> {
+    let mut lines = recipients.to_vec();
+    for file in recipients_files {
+        let content = fs::read_to_string(file)?;
+        lines.extend(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(String::from),
+        );
+    }
+    lines
+        .iter()
+        .map(|r| {
+            r.parse::<x25519::Recipient>()
+                .map(|r| Box::new(r) as Box<dyn age::Recipient + Send>)
+                .map_err(|e| anyhow::anyhow!("Invalid recipient {}: {}", r, e))
+        })
+        .collect()
+}
+
+fn load_identities(identity_files: &[String]) -> Result<Vec<Box<dyn age::Identity>>> {
+    let mut identities: Vec<Box<dyn age::Identity>> = Vec::new();
+    for file in identity_files {
+        for entry in IdentityFile::from_file(file.clone())?.into_identities() {
+            match entry {
+                IdentityFileEntry::Native(identity) => identities.push(Box::new(identity)),
+            }
+        }
+    }
+    Ok(identities)
+}
+
+/// Encrypt `input` into an age file, to X25519 recipients or, without
+/// recipients, with a passphrase (scrypt stanza). The output is armored
+/// unless `binary` is set.
+pub async fn process_age_encrypt(
+    input: &str,
+    output: &str,
+    recipients: &[String],
+    recipients_files: &[String],
+    binary: bool,
+    passphrase_file: Option<&str>,
+) -> Result<()> {
+    let recipients = parse_recipients(recipients, recipients_files)?;
+    let encryptor = if recipients.is_empty() {
+        let passphrase = read_passphrase(passphrase_file, true)?;
+        Encryptor::with_user_passphrase(SecretString::new(passphrase.to_string()))
+    } else {
+        Encryptor::with_recipients(recipients)
+            .ok_or_else(|| anyhow::anyhow!("At least one recipient is required"))?
+    };
+    let format = if binary {
+        Format::Binary
+    } else {
+        Format::AsciiArmor
+    };
+    let mut reader = get_reader(input)?;
+    let armored = ArmoredWriter::wrap_output(get_writer(output)?, format)?;
+    let mut writer = encryptor.wrap_output(armored)?;
+    io::copy(&mut reader, &mut writer)?;
+    writer.finish()?.finish()?.flush()?;
+    Ok(())
+}
+
+/// Decrypt an age file from `reader` with the identities in
+/// `identity_files`, or with a passphrase for scrypt-encrypted files.
+pub(crate) fn age_decrypt(
+    reader: impl Read,
+    writer: &mut dyn io::Write,
+    identity_files: &[String],
+    passphrase_file: Option<&str>,
+) -> Result<()> {
+    let reader = ArmoredReader::new(BufReader::new(reader));
+    let mut plaintext = match Decryptor::new_buffered(reader)? {
+        Decryptor::Recipients(decryptor) => {
+            if identity_files.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Message is encrypted to recipients, an identity file is required"
+                ));
+            }
+            let identities = load_identities(identity_files)?;
+            decryptor.decrypt(identities.iter().map(|i| i.as_ref()))?
+        }
+        Decryptor::Passphrase(decryptor) => {
+            let passphrase = read_passphrase(passphrase_file, false)?;
+            decryptor.decrypt(&SecretString::new(passphrase.to_string()), None)?
+        }
+    };
+    io::copy(&mut plaintext, writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_age_recipient_round_trip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let identity = dir.path().join("age_identity.txt");
+        let encrypted = dir.path().join("age.enc");
+        let identity = identity.to_str().unwrap();
+        let encrypted = encrypted.to_str().unwrap();
+        let recipient = process_gen_identity(identity).await?;
+        assert!(recipient.starts_with("age1"));
+
+        process_age_encrypt("Cargo.toml", encrypted, &[recipient], &[], false, None).await?;
+        let armored = fs::read(encrypted)?;
+        assert!(is_age(&armored[..AGE_PEEK_LEN]));
+
+        let mut decrypted = Vec::new();
+        age_decrypt(armored.as_slice(), &mut decrypted, &[identity.into()], None)?;
+        assert_eq!(decrypted, fs::read("Cargo.toml")?);
+
+        // no identity, or the wrong one, must fail
+        assert!(age_decrypt(armored.as_slice(), &mut Vec::new(), &[], None).is_err());
+        let other = dir.path().join("age_other.txt");
+        let other = other.to_str().unwrap();
+        process_gen_identity(other).await?;
+        assert!(age_decrypt(armored.as_slice(), &mut Vec::new(), &[other.into()], None).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_age_passphrase_round_trip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let passphrase = dir.path().join("age_passphrase.txt");
+        let encrypted = dir.path().join("age_scrypt.age");
+        fs::write(&passphrase, "correct horse\n")?;
+        let passphrase = passphrase.to_str();
+        let encrypted = encrypted.to_str().unwrap();
+
+        process_age_encrypt("Cargo.toml", encrypted, &[], &[], true, passphrase).await?;
+        let binary = fs::read(encrypted)?;
+        assert!(binary.starts_with(AGE_MAGIC));
+
+        let mut decrypted = Vec::new();
+        age_decrypt(binary.as_slice(), &mut decrypted, &[], passphrase)?;
+        assert_eq!(decrypted, fs::read("Cargo.toml")?);
+        Ok(())
+    }
+}
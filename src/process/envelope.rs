@@ -0,0 +1,322 @@
+use crate::{argon2_params, derive_passphrase_key, Cipher};
+use anyhow::Result;
+use argon2::Params;
+use pem_rfc7468::LineEnding;
+use rand::{rngs::OsRng, RngCore};
+use std::io::Read;
+
+pub(crate) const MAGIC: &[u8; 4] = b"RCLI";
+const ARMOR_LABEL: &str = "RCLI ENCRYPTED MESSAGE";
+const VERSION: u8 = 1;
+const KEY_ID_CONTEXT: &str = "rcli 2024-05-01 key id";
+/// The key is derived from a password; a KDF section follows the nonce.
+const FLAG_PASSWORD: u8 = 0x01;
+/// Associated data outside the message is required to decrypt it.
+const FLAG_AAD: u8 = 0x02;
+
+impl Cipher {
+    /// Algorithm id recorded in the envelope header.
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Cipher::ChaCha20Poly1305 => 1,
+            Cipher::XChaCha20Poly1305 => 2,
+            Cipher::Aes256Gcm => 3,
+            Cipher::Aes256GcmSiv => 4,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self> {
+        match id {
+            1 => Ok(Cipher::ChaCha20Poly1305),
+            2 => Ok(Cipher::XChaCha20Poly1305),
+            3 => Ok(Cipher::Aes256Gcm),
+            4 => Ok(Cipher::Aes256GcmSiv),
+            _ => Err(anyhow::anyhow!("Unsupported algorithm id {}", id)),
+        }
+    }
+}
+
+/// How the payload following the header is laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EnvelopeMode {
+    /// One ciphertext with its tag, up to the end of input.
+    Single = 0,
+    /// STREAM segments of 64 KiB plaintext, each with its own tag.
+    Stream = 1,
+}
+
+/// Header of an encrypted message. The binary layout is
+///
+/// ```text
+/// magic     4  "RCLI"
+/// version   1  1
+/// algorithm 1  see `Cipher::id`
+/// mode      1  see `EnvelopeMode`
+/// flags     1  0x01 password, 0x02 external AAD, other bits reserved
+/// key id    8  identifies the key, see `key_id`
+/// nonce len 1
+/// nonce     n  full nonce, or the nonce prefix in stream mode
+/// ```
+///
+/// With the password flag, an Argon2id section follows:
+///
+/// ```text
+/// m cost    4  big-endian, KiB
+/// t cost    4  big-endian
+/// p cost    4  big-endian
+/// salt len  1
+/// salt      n
+/// ```
+///
+/// followed by the payload. The encoded header is the associated data of
+/// every AEAD operation, so none of its fields can be altered. With the
+/// external AAD flag, the caller's associated data is appended to it; it is
+/// not stored, so decryption needs the same value again. The armored
+/// form is the same bytes as base64 between `RCLI ENCRYPTED MESSAGE` lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Envelope {
+    pub cipher: Cipher,
+    pub mode: EnvelopeMode,
+    pub flags: u8,
+    pub key_id: [u8; 8],
+    pub nonce: Vec<u8>,
+    pub kdf: Option<KdfParams>,
+}
+
+/// Argon2id parameters of a password-derived key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct KdfParams {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+    pub salt: Vec<u8>,
+}
+
+impl KdfParams {
+    /// Default Argon2id costs with a fresh random salt.
+    pub(crate) fn generate() -> Self {
+        let params = Params::default();
+        let mut salt = vec![0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        KdfParams {
+            m_cost: params.m_cost(),
+            t_cost: params.t_cost(),
+            p_cost: params.p_cost(),
+            salt,
+        }
+    }
+
+    pub(crate) fn derive_key(&self, password: &str) -> Result<[u8; 32]> {
+        let params = argon2_params(self.m_cost, self.t_cost, self.p_cost)?;
+        derive_passphrase_key(password, &self.salt, params)
+    }
+}
+
+/// A short public identifier of a symmetric key, derived so that it reveals
+/// nothing about the key itself.
+pub(crate) fn key_id(key: &[u8]) -> [u8; 8] {
+    let id = blake3::derive_key(KEY_ID_CONTEXT, key);
+    id[..8].try_into().unwrap()
+}
+
+impl Envelope {
+    pub(crate) fn new(cipher: Cipher, mode: EnvelopeMode, key_id: [u8; 8], nonce: &[u8]) -> Self {
+        Envelope {
+            cipher,
+            mode,
+            flags: 0,
+            key_id,
+            nonce: nonce.to_vec(),
+            kdf: None,
+        }
+    }
+
+    /// Record that decryption needs external associated data.
+    pub(crate) fn with_aad(mut self) -> Self {
+        self.flags |= FLAG_AAD;
+        self
+    }
+
+    pub(crate) fn has_aad(&self) -> bool {
+        self.flags & FLAG_AAD != 0
+    }
+
+    /// Record that the key was derived from a password with `kdf`.
+    pub(crate) fn with_kdf(mut self, kdf: KdfParams) -> Self {
+        self.flags |= FLAG_PASSWORD;
+        self.kdf = Some(kdf);
+        self
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(17 + self.nonce.len());
+        buf.extend_from_slice(MAGIC);
+        buf.push(VERSION);
+        buf.push(self.cipher.id());
+        buf.push(self.mode as u8);
+        buf.push(self.flags);
+        buf.extend_from_slice(&self.key_id);
+        buf.push(self.nonce.len() as u8);
+        buf.extend_from_slice(&self.nonce);
+        if let Some(kdf) = &self.kdf {
+            buf.extend(kdf.m_cost.to_be_bytes());
+            buf.extend(kdf.t_cost.to_be_bytes());
+            buf.extend(kdf.p_cost.to_be_bytes());
+            buf.push(kdf.salt.len() as u8);
+            buf.extend_from_slice(&kdf.salt);
+        }
+        buf
+    }
+
+    /// Read a header from the start of `reader`, leaving it at the payload.
+    pub(crate) fn read_from(reader: &mut dyn Read) -> Result<Self> {
+        let truncated = |_| anyhow::anyhow!("Encrypted message header is truncated");
+        let mut fixed = [0u8; 17];
+        reader.read_exact(&mut fixed).map_err(truncated)?;
+        if &fixed[..4] != MAGIC {
+            return Err(anyhow::anyhow!("Not an rcli encrypted message"));
+        }
+        if fixed[4] != VERSION {
+            return Err(anyhow::anyhow!("Unsupported message version {}", fixed[4]));
+        }
+        let cipher = Cipher::from_id(fixed[5])?;
+        let mode = match fixed[6] {
+            0 => EnvelopeMode::Single,
+            1 => EnvelopeMode::Stream,
+            m => return Err(anyhow::anyhow!("Unsupported message mode {}", m)),
+        };
+        let flags = fixed[7];
+        if flags & !(FLAG_PASSWORD | FLAG_AAD) != 0 {
+            return Err(anyhow::anyhow!("Unsupported message flags {:#04x}", flags));
+        }
+        let key_id = fixed[8..16].try_into()?;
+        let mut nonce = vec![0u8; fixed[16] as usize];
+        reader.read_exact(&mut nonce).map_err(truncated)?;
+        let kdf = if flags & FLAG_PASSWORD != 0 {
+            let mut costs = [0u8; 13];
+            reader.read_exact(&mut costs).map_err(truncated)?;
+            let cost = |i: usize| u32::from_be_bytes(costs[i * 4..i * 4 + 4].try_into().unwrap());
+            let mut salt = vec![0u8; costs[12] as usize];
+            reader.read_exact(&mut salt).map_err(truncated)?;
+            Some(KdfParams {
+                m_cost: cost(0),
+                t_cost: cost(1),
+                p_cost: cost(2),
+                salt,
+            })
+        } else {
+            None
+        };
+        Ok(Envelope {
+            cipher,
+            mode,
+            flags,
+            key_id,
+            nonce,
+            kdf,
+        })
+    }
+
+    /// Fail early with a readable error when the message is for another key.
+    pub(crate) fn check_key_id(&self, key_id: [u8; 8]) -> Result<()> {
+        if self.key_id != key_id {
+            if self.kdf.is_some() {
+                return Err(anyhow::anyhow!("Wrong password"));
+            }
+            return Err(anyhow::anyhow!(
+                "Message was encrypted for key id {}, but the given key has id {}",
+                hex::encode(self.key_id),
+                hex::encode(key_id)
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Whether `text` looks like an armored envelope.
+pub(crate) fn is_armored(text: &str) -> bool {
+    text.trim_start()
+        .starts_with(&format!("-----BEGIN {}-----", ARMOR_LABEL))
+}
+
+pub(crate) fn encode_armor(message: &[u8]) -> Result<String> {
+    Ok(pem_rfc7468::encode_string(
+        ARMOR_LABEL,
+        LineEnding::LF,
+        message,
+    )?)
+}
+
+pub(crate) fn decode_armor(text: &str) -> Result<Vec<u8>> {
+    let (label, message) = pem_rfc7468::decode_vec(text.trim().as_bytes())
+        .map_err(|_| anyhow::anyhow!("Malformed armored message"))?;
+    if label != ARMOR_LABEL {
+        return Err(anyhow::anyhow!("Unexpected armor label {}", label));
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_round_trip() -> Result<()> {
+        let envelope = Envelope::new(
+            Cipher::ChaCha20Poly1305,
+            EnvelopeMode::Stream,
+            key_id(b"key"),
+            &[1, 2, 3, 4, 5, 6, 7],
+        );
+        let mut buf = envelope.to_bytes();
+        buf.extend_from_slice(b"payload");
+        let mut reader = buf.as_slice();
+        assert_eq!(Envelope::read_from(&mut reader)?, envelope);
+        assert_eq!(reader, b"payload");
+
+        let envelope = envelope.with_kdf(KdfParams::generate());
+        let buf = envelope.to_bytes();
+        assert_eq!(buf[7], FLAG_PASSWORD);
+        assert_eq!(Envelope::read_from(&mut buf.as_slice())?, envelope);
+        Ok(())
+    }
+
+    #[test]
+    fn test_envelope_rejects_malformed_header() {
+        let buf = Envelope::new(
+            Cipher::ChaCha20Poly1305,
+            EnvelopeMode::Single,
+            key_id(b"key"),
+            &[0; 12],
+        )
+        .to_bytes();
+        assert!(Envelope::read_from(&mut &buf[..20]).is_err());
+
+        let mut bad_version = buf.clone();
+        bad_version[4] = 9;
+        assert!(Envelope::read_from(&mut bad_version.as_slice()).is_err());
+
+        let mut bad_alg = buf.clone();
+        bad_alg[5] = 200;
+        assert!(Envelope::read_from(&mut bad_alg.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_kdf_params_capped() {
+        let kdf = KdfParams {
+            m_cost: u32::MAX,
+            ..KdfParams::generate()
+        };
+        assert!(kdf.derive_key("password").is_err());
+        let kdf = KdfParams {
+            t_cost: 1000,
+            ..KdfParams::generate()
+        };
+        assert!(kdf.derive_key("password").is_err());
+        let kdf = KdfParams {
+            p_cost: 1 << 20,
+            ..KdfParams::generate()
+        };
+        assert!(kdf.derive_key("password").is_err());
+    }
+}
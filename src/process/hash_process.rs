@@ -0,0 +1,245 @@
+use crate::{get_reader, list_files, HashAlgorithm};
+use anyhow::Result;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use sha3::{Sha3_256, Sha3_384, Sha3_512};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use xxhash_rust::xxh3::Xxh3;
+use xxhash_rust::xxh64::Xxh64;
+
+const BUF_SIZE: usize = 64 * 1024;
+
+/// Result of checking one line of a checksum file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+    /// The file could not be opened or read.
+    Unreadable,
+}
+
+/// Result of checking a checksum file.
+#[derive(Debug, Default)]
+pub struct HashCheck {
+    /// Each listed path with its status, in file order.
+    pub entries: Vec<(String, CheckStatus)>,
+    /// Line numbers of improperly formatted lines, which are skipped.
+    pub malformed: Vec<usize>,
+}
+
+/// Feed everything read from `reader` to `update`, one buffer at a time.
+pub(crate) fn read_all(reader: &mut dyn Read, mut update: impl FnMut(&[u8])) -> Result<()> {
+    let mut buf = vec![0u8; BUF_SIZE];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => update(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn digest<D: Digest>(reader: &mut dyn Read) -> Result<String> {
+    let mut hasher = D::new();
+    read_all(reader, |buf| hasher.update(buf))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Hash everything read from `reader` and return the hex digest.
+pub fn hash_reader(algorithm: HashAlgorithm, reader: &mut dyn Read) -> Result<String> {
+    match algorithm {
+        HashAlgorithm::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            hasher.update_reader(reader)?;
+            Ok(hasher.finalize().to_hex().to_string())
+        }
+        HashAlgorithm::Sha256 => digest::<Sha256>(reader),
+        HashAlgorithm::Sha384 => digest::<Sha384>(reader),
+        HashAlgorithm::Sha512 => digest::<Sha512>(reader),
+        HashAlgorithm::Sha3_256 => digest::<Sha3_256>(reader),
+        HashAlgorithm::Sha3_384 => digest::<Sha3_384>(reader),
+        HashAlgorithm::Sha3_512 => digest::<Sha3_512>(reader),
+        HashAlgorithm::Xxh64 => {
+            let mut hasher = Xxh64::new(0);
+            read_all(reader, |buf| hasher.update(buf))?;
+            Ok(format!("{:016x}", hasher.digest()))
+        }
+        HashAlgorithm::Xxh3 => {
+            let mut hasher = Xxh3::new();
+            read_all(reader, |buf| hasher.update(buf))?;
+            Ok(format!("{:016x}", hasher.digest()))
+        }
+        HashAlgorithm::Xxh3_128 => {
+            let mut hasher = Xxh3::new();
+            read_all(reader, |buf| hasher.update(buf))?;
+            Ok(format!("{:032x}", hasher.digest128()))
+        }
+    }
+}
+
+/// Hash a file. blake3 memory-maps large files and hashes them on all cores.
+pub fn hash_file(algorithm: HashAlgorithm, path: &Path) -> Result<String> {
+    match algorithm {
+        HashAlgorithm::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            hasher.update_mmap_rayon(path)?;
+            Ok(hasher.finalize().to_hex().to_string())
+        }
+        _ => hash_reader(algorithm, &mut fs::File::open(path)?),
+    }
+}
+
+/// Expand `paths` into the inputs to hash: `-` is stdin, and directories are
+/// walked when `recursive` is set.
+fn expand_paths(paths: &[String], recursive: bool) -> Result<Vec<String>> {
+    let mut res = Vec::new();
+    for path in paths {
+        if path != "-" && Path::new(path).is_dir() {
+            if !recursive {
+                return Err(anyhow::anyhow!(
+                    "{} is a directory, use --recursive to hash its files",
+                    path
+                ));
+            }
+            let dir = path.trim_end_matches('/');
+            res.extend(
+                list_files(Path::new(path))?
+                    .into_iter()
+                    .map(|file| format!("{}/{}", dir, file)),
+            );
+        } else {
+            res.push(path.clone());
+        }
+    }
+    Ok(res)
+}
+
+/// Hash every input, several files in parallel, and return `(digest, path)`
+/// pairs in input order.
+pub async fn process_hash(
+    paths: &[String],
+    algorithm: HashAlgorithm,
+    recursive: bool,
+) -> Result<Vec<(String, String)>> {
+    expand_paths(paths, recursive)?
+        .into_par_iter()
+        .map(|path| {
+            let digest = if path == "-" {
+                hash_reader(algorithm, &mut io::stdin())?
+            } else {
+                hash_file(algorithm, Path::new(&path))?
+            };
+            Ok((digest, path))
+        })
+        .collect()
+}
+
+/// Split a `sha256sum`-style line, `<digest>  <path>` or `<digest> *<path>`
+/// in binary mode.
+fn parse_checksum_line(line: &str) -> Option<(&str, &str)> {
+    let (digest, rest) = line.split_once(' ')?;
+    let path = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*'))?;
+    if digest.is_empty() || path.is_empty() {
+        return None;
+    }
+    Some((digest, path))
+}
+
+/// Verify the files listed in a checksum file. Paths are relative to the
+/// current directory, as with `sha256sum --check`. Improperly formatted lines
+/// are skipped and reported instead of aborting the check.
+pub async fn process_hash_check(
+    checksum_file: &str,
+    algorithm: HashAlgorithm,
+) -> Result<HashCheck> {
+    let reader = BufReader::new(get_reader(checksum_file)?);
+    let mut entries = Vec::new();
+    let mut malformed = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_checksum_line(line) {
+            Some((digest, path)) => entries.push((digest.to_ascii_lowercase(), path.to_string())),
+            None => malformed.push(i + 1),
+        }
+    }
+    let entries = entries
+        .into_par_iter()
+        .map(|(expected, path)| {
+            let status = match hash_file(algorithm, Path::new(&path)) {
+                Ok(actual) if actual == expected => CheckStatus::Ok,
+                Ok(_) => CheckStatus::Failed,
+                Err(_) => CheckStatus::Unreadable,
+            };
+            (path, status)
+        })
+        .collect();
+    Ok(HashCheck { entries, malformed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_reader() -> Result<()> {
+        let cases = [
+            (
+                HashAlgorithm::Sha256,
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                HashAlgorithm::Sha3_256,
+                "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532",
+            ),
+            (
+                HashAlgorithm::Blake3,
+                "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+            ),
+            (HashAlgorithm::Xxh64, "44bc2cf5ad770999"),
+        ];
+        for (algorithm, expected) in cases {
+            assert_eq!(hash_reader(algorithm, &mut b"abc".as_slice())?, expected);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hash_check() -> Result<()> {
+        let paths = ["fixture/test".to_string()];
+        assert!(process_hash(&paths, HashAlgorithm::Sha256, false)
+            .await
+            .is_err());
+        let res = process_hash(&paths, HashAlgorithm::Blake3, true).await?;
+        assert!(res.iter().any(|(_, path)| path == "fixture/test/test1.txt"));
+
+        let dir = tempfile::tempdir()?;
+        let checksums = dir.path().join("checksums.txt");
+        let mut lines: Vec<_> = res.iter().map(|(d, p)| format!("{}  {}", d, p)).collect();
+        lines.push(format!("{} *fixture/test/missing.txt", res[0].0));
+        lines.insert(1, "not a checksum line".to_string());
+        fs::write(&checksums, lines.join("\n"))?;
+        let checksums = checksums.to_str().unwrap();
+
+        let res = process_hash_check(checksums, HashAlgorithm::Blake3).await?;
+        assert_eq!(res.malformed, [2]);
+        let statuses: Vec<_> = res.entries.iter().map(|(_, status)| *status).collect();
+        assert_eq!(statuses.last(), Some(&CheckStatus::Unreadable));
+        assert!(statuses[..statuses.len() - 1]
+            .iter()
+            .all(|s| *s == CheckStatus::Ok));
+
+        let res = process_hash_check(checksums, HashAlgorithm::Sha256).await?;
+        assert!(res
+            .entries
+            .iter()
+            .all(|(_, status)| *status != CheckStatus::Ok));
+        Ok(())
+    }
+}
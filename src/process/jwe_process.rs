@@ -0,0 +1,496 @@
+use crate::{decode_key, read_key_file, JweAlg, JweEnc};
+use aes::cipher::{BlockDecrypt, BlockEncrypt};
+use aes::Aes256;
+use aes_gcm::Aes256Gcm;
+use anyhow::Result;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::ChaCha20Poly1305;
+use p256::ecdh::{diffie_hellman, EphemeralSecret};
+use p256::elliptic_curve::sec1::{EncodedPoint, FromEncodedPoint, ToEncodedPoint};
+use p256::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use p256::{NistP256, PublicKey, SecretKey};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+const CEK_LEN: usize = 32;
+const IV_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+/// Initial value of RFC 3394 key wrap.
+const KW_IV: [u8; 8] = [0xa6; 8];
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JweHeader {
+    alg: String,
+    enc: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cty: Option<String>,
+    /// Ephemeral public key of ECDH-ES, as a JWK.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epk: Option<EphemeralKey>,
+    /// Never written; read only to refuse tokens that need extensions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crit: Option<serde_json::Value>,
+    /// Never written; read only to refuse compressed tokens.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    zip: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EphemeralKey {
+    kty: String,
+    crv: String,
+    x: String,
+    y: String,
+}
+
+impl EphemeralKey {
+    fn new(key: &PublicKey) -> Self {
+        let point = key.to_encoded_point(false);
+        Self {
+            kty: "EC".into(),
+            crv: "P-256".into(),
+            x: URL_SAFE_NO_PAD.encode(point.x().unwrap()),
+            y: URL_SAFE_NO_PAD.encode(point.y().unwrap()),
+        }
+    }
+
+    fn public_key(&self) -> Result<PublicKey> {
+        if self.kty != "EC" || self.crv != "P-256" {
+            return Err(anyhow::anyhow!("Only P-256 ephemeral keys are supported"));
+        }
+        let x = URL_SAFE_NO_PAD.decode(&self.x)?;
+        let y = URL_SAFE_NO_PAD.decode(&self.y)?;
+        if x.len() != 32 || y.len() != 32 {
+            return Err(anyhow::anyhow!("Invalid ephemeral key"));
+        }
+        let point =
+            EncodedPoint::<NistP256>::from_affine_coordinates(x[..].into(), y[..].into(), false);
+        Option::from(PublicKey::from_encoded_point(&point))
+            .ok_or_else(|| anyhow::anyhow!("Ephemeral key is not on P-256"))
+    }
+}
+
+/// RFC 3394 AES key wrap with a 256-bit key-encryption key.
+fn aes_key_wrap(kek: &[u8; 32], key: &[u8]) -> Vec<u8> {
+    let cipher = Aes256::new(kek.into());
+    let mut a = KW_IV;
+    let mut r: Vec<[u8; 8]> = key.chunks_exact(8).map(|c| c.try_into().unwrap()).collect();
+    let n = r.len();
+    let mut block = [0u8; 16];
+    for j in 0..6 {
+        for (i, ri) in r.iter_mut().enumerate() {
+            block[..8].copy_from_slice(&a);
+            block[8..].copy_from_slice(ri);
+            cipher.encrypt_block((&mut block).into());
+            let t = (n * j + i + 1) as u64;
+            a = (u64::from_be_bytes(block[..8].try_into().unwrap()) ^ t).to_be_bytes();
+            ri.copy_from_slice(&block[8..]);
+        }
+    }
+    let mut res = a.to_vec();
+    r.iter().for_each(|ri| res.extend_from_slice(ri));
+    res
+}
+
+/// Reverse [`aes_key_wrap`], failing when the integrity check does not hold.
+fn aes_key_unwrap(kek: &[u8; 32], wrapped: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    if wrapped.len() < 24 || !wrapped.len().is_multiple_of(8) {
+        return Err(anyhow::anyhow!("Invalid wrapped key length"));
+    }
+    let cipher = Aes256::new(kek.into());
+    let mut a: [u8; 8] = wrapped[..8].try_into().unwrap();
+    let mut r: Vec<[u8; 8]> = wrapped[8..]
+        .chunks_exact(8)
+        .map(|c| c.try_into().unwrap())
+        .collect();
+    let n = r.len();
+    let mut block = [0u8; 16];
+    for j in (0..6).rev() {
+        for (i, ri) in r.iter_mut().enumerate().rev() {
+            let t = (n * j + i + 1) as u64;
+            block[..8].copy_from_slice(&(u64::from_be_bytes(a) ^ t).to_be_bytes());
+            block[8..].copy_from_slice(ri);
+            cipher.decrypt_block((&mut block).into());
+            a.copy_from_slice(&block[..8]);
+            ri.copy_from_slice(&block[8..]);
+        }
+    }
+    if a != KW_IV {
+        return Err(anyhow::anyhow!("Key unwrap failed, wrong key?"));
+    }
+    Ok(Zeroizing::new(r.concat()))
+}
+
+/// Concat KDF of RFC 7518, section 4.6.2, with SHA-256.
+fn concat_kdf(z: &[u8], alg_id: &str, apu: &[u8], apv: &[u8], key_len: usize) -> Vec<u8> {
+    let mut other_info = Vec::new();
+    for field in [alg_id.as_bytes(), apu, apv] {
+        other_info.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        other_info.extend_from_slice(field);
+    }
+    other_info.extend_from_slice(&((key_len * 8) as u32).to_be_bytes());
+    let mut key = Vec::with_capacity(key_len);
+    for counter in 1u32.. {
+        if key.len() >= key_len {
+            break;
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(counter.to_be_bytes());
+        hasher.update(z);
+        hasher.update(&other_info);
+        key.extend_from_slice(&hasher.finalize());
+    }
+    key.truncate(key_len);
+    key
+}
+
+fn ecdh_es_key(shared: &[u8], enc: JweEnc) -> Zeroizing<Vec<u8>> {
+    Zeroizing::new(concat_kdf(shared, enc.into(), &[], &[], CEK_LEN))
+}
+
+fn seal_with<A: AeadInPlace + KeyInit>(
+    cek: &[u8],
+    iv: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let cipher = A::new_from_slice(cek).map_err(|_| anyhow::anyhow!("Invalid content key"))?;
+    let mut buf = plaintext.to_vec();
+    let tag = cipher
+        .encrypt_in_place_detached(iv.into(), aad, &mut buf)
+        .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+    Ok((buf, tag.to_vec()))
+}
+
+fn open_with<A: AeadInPlace + KeyInit>(
+    cek: &[u8],
+    iv: &[u8],
+    aad: &[u8],
+    ciphertext: &[u8],
+    tag: &[u8],
+) -> Result<Vec<u8>> {
+    let cipher = A::new_from_slice(cek).map_err(|_| anyhow::anyhow!("Invalid content key"))?;
+    let mut buf = ciphertext.to_vec();
+    cipher
+        .decrypt_in_place_detached(iv.into(), aad, &mut buf, tag.into())
+        .map_err(|_| anyhow::anyhow!("Decryption failed, wrong key or tampered token"))?;
+    Ok(buf)
+}
+
+fn read_symmetric_key(path: &str) -> Result<Zeroizing<[u8; 32]>> {
+    Ok(Zeroizing::new(decode_key(&read_key_file(path, None)?)?))
+}
+
+fn ec_der(data: &[u8]) -> Result<Vec<u8>> {
+    match std::str::from_utf8(data).map(str::trim) {
+        Ok(text) if text.starts_with("-----BEGIN ") => Ok(pem_rfc7468::decode_vec(text.as_bytes())
+            .map_err(|e| anyhow::anyhow!("Invalid PEM key: {}", e))?
+            .1),
+        _ => Ok(data.to_vec()),
+    }
+}
+
+/// A P-256 public key, or the public half of a private key.
+fn read_ec_public_key(path: &str) -> Result<PublicKey> {
+    let der = ec_der(&read_key_file(path, None)?)?;
+    PublicKey::from_public_key_der(&der)
+        .ok()
+        .or_else(|| read_ec_secret_key(&der).ok().map(|key| key.public_key()))
+        .ok_or_else(|| anyhow::anyhow!("ECDH-ES needs a P-256 key"))
+}
+
+fn read_ec_secret_key(der: &[u8]) -> Result<SecretKey> {
+    SecretKey::from_pkcs8_der(der)
+        .or_else(|_| SecretKey::from_sec1_der(der))
+        .map_err(|_| anyhow::anyhow!("ECDH-ES needs a P-256 private key to decrypt"))
+}
+
+/// Encrypt `reader` into a JWE in compact serialization. `key` is a 32-byte
+/// key file for `dir` and `A256KW`, or the recipient's P-256 key for
+/// `ECDH-ES`.
+pub async fn process_jwe_encrypt(
+    reader: &mut dyn Read,
+    alg: JweAlg,
+    enc: JweEnc,
+    key: &str,
+    cty: Option<String>,
+) -> Result<String> {
+    let mut plaintext = Zeroizing::new(Vec::new());
+    reader.read_to_end(&mut plaintext)?;
+
+    let mut header = JweHeader {
+        alg: alg.to_string(),
+        enc: enc.to_string(),
+        cty,
+        epk: None,
+        crit: None,
+        zip: None,
+    };
+    let (cek, encrypted_key) = match alg {
+        JweAlg::Dir => (
+            Zeroizing::new(read_symmetric_key(key)?.to_vec()),
+            Vec::new(),
+        ),
+        JweAlg::A256Kw => {
+            let kek = read_symmetric_key(key)?;
+            let mut cek = Zeroizing::new(vec![0u8; CEK_LEN]);
+            OsRng.fill_bytes(&mut cek);
+            let wrapped = aes_key_wrap(&kek, &cek);
+            (cek, wrapped)
+        }
+        JweAlg::EcdhEs => {
+            let recipient = read_ec_public_key(key)?;
+            let ephemeral = EphemeralSecret::random(&mut OsRng);
+            header.epk = Some(EphemeralKey::new(&ephemeral.public_key()));
+            let shared = ephemeral.diffie_hellman(&recipient);
+            (ecdh_es_key(shared.raw_secret_bytes(), enc), Vec::new())
+        }
+    };
+
+    let protected = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?);
+    let mut iv = [0u8; IV_LEN];
+    OsRng.fill_bytes(&mut iv);
+    let (ciphertext, tag) = match enc {
+        JweEnc::A256Gcm => seal_with::<Aes256Gcm>(&cek, &iv, protected.as_bytes(), &plaintext)?,
+        JweEnc::C20P => seal_with::<ChaCha20Poly1305>(&cek, &iv, protected.as_bytes(), &plaintext)?,
+    };
+    let parts = [&encrypted_key[..], &iv, &ciphertext, &tag].map(|p| URL_SAFE_NO_PAD.encode(p));
+    Ok(format!("{}.{}", protected, parts.join(".")))
+}
+
+/// Parse an algorithm name from a JWE header, which must be spelled exactly as
+/// registered, and check that it is accepted.
+fn header_alg<T>(name: &str, accepted: &[T], what: &str) -> Result<T>
+where
+    T: FromStr + Copy + PartialEq + Into<&'static str>,
+{
+    let alg = T::from_str(name)
+        .ok()
+        .filter(|alg| Into::<&str>::into(*alg) == name)
+        .ok_or_else(|| anyhow::anyhow!("Unsupported JWE {} {:?}", what, name))?;
+    if !accepted.contains(&alg) {
+        return Err(anyhow::anyhow!("JWE {} {} is not accepted", what, name));
+    }
+    Ok(alg)
+}
+
+/// Decrypt a compact JWE. Its header must name one of the accepted `algs`
+/// and `encs`, and `key` must be of the matching kind: a 32-byte key file for
+/// `dir` and `A256KW`, a P-256 private key for `ECDH-ES`.
+pub async fn process_jwe_decrypt(
+    token: &str,
+    key: &str,
+    algs: &[JweAlg],
+    encs: &[JweEnc],
+) -> Result<Zeroizing<Vec<u8>>> {
+    let parts: Vec<_> = token.split('.').collect();
+    if parts.len() != 5 {
+        return Err(anyhow::anyhow!(
+            "Expected a JWE of five dot-separated parts, got {}",
+            parts.len()
+        ));
+    }
+    let decoded = parts[1..]
+        .iter()
+        .map(|p| URL_SAFE_NO_PAD.decode(p))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow::anyhow!("Invalid JWE encoding: {}", e))?;
+    let (encrypted_key, iv, ciphertext, tag) = (&decoded[0], &decoded[1], &decoded[2], &decoded[3]);
+    let header: JweHeader = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(parts[0])?)
+        .map_err(|e| anyhow::anyhow!("Invalid JWE header: {}", e))?;
+    let alg: JweAlg = header_alg(&header.alg, algs, "alg")?;
+    let enc: JweEnc = header_alg(&header.enc, encs, "enc")?;
+    if header.crit.is_some() {
+        return Err(anyhow::anyhow!("JWE crit extensions are not supported"));
+    }
+    if header.zip.is_some() {
+        return Err(anyhow::anyhow!("Compressed JWEs are not supported"));
+    }
+    if iv.len() != IV_LEN || tag.len() != TAG_LEN {
+        return Err(anyhow::anyhow!("Invalid IV or tag length for {}", enc));
+    }
+
+    let cek = match alg {
+        JweAlg::Dir => Zeroizing::new(read_symmetric_key(key)?.to_vec()),
+        JweAlg::A256Kw => aes_key_unwrap(&*read_symmetric_key(key)?, encrypted_key)?,
+        JweAlg::EcdhEs => {
+            let epk = header
+                .epk
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("ECDH-ES header has no epk"))?;
+            let secret = read_ec_secret_key(&ec_der(&read_key_file(key, None)?)?)?;
+            let shared = diffie_hellman(secret.to_nonzero_scalar(), epk.public_key()?.as_affine());
+            ecdh_es_key(shared.raw_secret_bytes(), enc)
+        }
+    };
+    if alg != JweAlg::A256Kw && !encrypted_key.is_empty() {
+        return Err(anyhow::anyhow!("{} must not carry an encrypted key", alg));
+    }
+
+    let aad = parts[0].as_bytes();
+    let plaintext = match enc {
+        JweEnc::A256Gcm => open_with::<Aes256Gcm>(&cek, iv, aad, ciphertext, tag)?,
+        JweEnc::C20P => open_with::<ChaCha20Poly1305>(&cek, iv, aad, ciphertext, tag)?,
+    };
+    Ok(Zeroizing::new(plaintext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGS: [JweAlg; 3] = [JweAlg::Dir, JweAlg::A256Kw, JweAlg::EcdhEs];
+    const ENCS: [JweEnc; 2] = [JweEnc::A256Gcm, JweEnc::C20P];
+
+    #[test]
+    fn test_aes_key_wrap() -> Result<()> {
+        // RFC 3394, section 4.6
+        let kek: [u8; 32] =
+            hex::decode("000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F")?
+                .try_into()
+                .unwrap();
+        let key = hex::decode("00112233445566778899AABBCCDDEEFF000102030405060708090A0B0C0D0E0F")?;
+        let wrapped = aes_key_wrap(&kek, &key);
+        assert_eq!(
+            hex::encode_upper(&wrapped),
+            "28C9F404C4B810F4CBCCB35CFB87F8263F5786E2D80ED326CBC7F0E71A99F43BFB988B9B7A02DD21"
+        );
+        assert_eq!(*aes_key_unwrap(&kek, &wrapped)?, key);
+        assert!(aes_key_unwrap(&[0u8; 32], &wrapped).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_concat_kdf() {
+        // RFC 7518, appendix C
+        let z = [
+            158, 86, 217, 29, 129, 113, 53, 211, 114, 131, 66, 131, 191, 132, 38, 156, 251, 49,
+            110, 163, 218, 128, 106, 72, 246, 218, 167, 121, 140, 254, 144, 196,
+        ];
+        let key = concat_kdf(&z, "A128GCM", b"Alice", b"Bob", 16);
+        assert_eq!(URL_SAFE_NO_PAD.encode(key), "VqqN6vgjbSBcIijNcacQGg");
+    }
+
+    #[tokio::test]
+    async fn test_jwe_round_trip() -> Result<()> {
+        let cases = [
+            (JweAlg::Dir, "fixture/text_key.txt", "fixture/text_key.txt"),
+            (
+                JweAlg::A256Kw,
+                "fixture/text_key.txt",
+                "fixture/text_key.txt",
+            ),
+            (
+                JweAlg::EcdhEs,
+                "fixture/jwt_ec.pub.pem",
+                "fixture/jwt_ec.pem",
+            ),
+        ];
+        let plaintext = b"eyJhbGciOiJIUzI1NiJ9.e30.sig";
+        for (alg, encrypt_key, decrypt_key) in cases {
+            for enc in [JweEnc::A256Gcm, JweEnc::C20P] {
+                let token = process_jwe_encrypt(
+                    &mut plaintext.as_slice(),
+                    alg,
+                    enc,
+                    encrypt_key,
+                    Some("JWT".into()),
+                )
+                .await?;
+                assert_eq!(token.split('.').count(), 5);
+                let decrypted = process_jwe_decrypt(&token, decrypt_key, &[alg], &[enc]).await?;
+                assert_eq!(decrypted.as_slice(), plaintext, "{} {}", alg, enc);
+
+                // only the accepted algorithms are decrypted
+                let other: Vec<_> = ALGS.into_iter().filter(|a| *a != alg).collect();
+                assert!(process_jwe_decrypt(&token, decrypt_key, &other, &[enc])
+                    .await
+                    .is_err());
+
+                // the protected header is authenticated
+                let (header, rest) = token.split_once('.').unwrap();
+                let mut json = URL_SAFE_NO_PAD.decode(header)?;
+                json.insert(1, b' ');
+                let tampered = format!("{}.{}", URL_SAFE_NO_PAD.encode(json), rest);
+                assert!(process_jwe_decrypt(&tampered, decrypt_key, &[alg], &[enc])
+                    .await
+                    .is_err());
+            }
+        }
+        assert!(
+            process_jwe_decrypt("a.b.c.d", "fixture/text_key.txt", &ALGS, &ENCS)
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_jwe_known_answer() -> Result<()> {
+        // made with another implementation (joserfc) from the fixture keys
+        let cases = [
+            (
+                "eyJhbGciOiJkaXIiLCJlbmMiOiJBMjU2R0NNIn0..a_RzYjynOAk-xsXE.HwbNLKpAT0NA5-va.\
+                 oC7Lam3J63h6WqjWR0xJoQ",
+                "fixture/text_key.txt",
+            ),
+            (
+                "eyJhbGciOiJBMjU2S1ciLCJlbmMiOiJBMjU2R0NNIn0.\
+                 vXBI8wU4bHNq1A0mQSDnd6k5N64416I3F_tWLSFw7nRdxWvubguihw.n-yL4hWovWIZxz1T.\
+                 zgeL1fFAH4pcpbep.OJkMmhnJBSK1C6jTCkB5BA",
+                "fixture/text_key.txt",
+            ),
+            (
+                "eyJhbGciOiJFQ0RILUVTIiwiZW5jIjoiQTI1NkdDTSIsImVwayI6eyJjcnYiOiJQLTI1NiIsIngiOiJf\
+                 ck9zbjgwYWM3czgtcE9neGFUNC00WTNvZEowUjVtZjdHWkdHSE5naFJVIiwieSI6ImFCSF9KcVd6UUxX\
+                 S3hiZDh3eDN0ME96ZUZ2QU85TEQzdVpvYUFhbVN1QVkiLCJrdHkiOiJFQyJ9fQ..hWLE5xoVUPYpj1_X.\
+                 _o5ntQlEJawsKTwg.j7bQN2Gh5LdYGarx6APzyw",
+                "fixture/jwt_ec.pem",
+            ),
+        ];
+        for (token, key) in cases {
+            let plaintext = process_jwe_decrypt(token, key, &ALGS, &ENCS).await?;
+            assert_eq!(plaintext.as_slice(), b"known answer");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_jwe_rejects_header() -> Result<()> {
+        // tokens properly sealed under each header, so only the header check fails
+        let key = "fixture/text_key.txt";
+        let cek = read_symmetric_key(key)?;
+        let seal = |header: &str| -> Result<String> {
+            let protected = URL_SAFE_NO_PAD.encode(header);
+            let iv = [0u8; IV_LEN];
+            let (ciphertext, tag) =
+                seal_with::<Aes256Gcm>(&*cek, &iv, protected.as_bytes(), b"hello")?;
+            let parts = [&iv[..], &ciphertext, &tag].map(|p| URL_SAFE_NO_PAD.encode(p));
+            Ok(format!("{}..{}", protected, parts.join(".")))
+        };
+        let token = seal(r#"{"alg":"dir","enc":"A256GCM"}"#)?;
+        assert_eq!(
+            process_jwe_decrypt(&token, key, &ALGS, &ENCS)
+                .await?
+                .as_slice(),
+            b"hello"
+        );
+        let headers = [
+            r#"{"alg":"DIR","enc":"A256GCM"}"#,
+            r#"{"alg":"dir","enc":"a256gcm"}"#,
+            r#"{"alg":"dir","enc":"A256GCM","crit":["exp"],"exp":0}"#,
+            r#"{"alg":"dir","enc":"A256GCM","zip":"DEF"}"#,
+        ];
+        for header in headers {
+            let res = process_jwe_decrypt(&seal(header)?, key, &ALGS, &ENCS).await;
+            assert!(res.is_err(), "{}", header);
+        }
+        Ok(())
+    }
+}
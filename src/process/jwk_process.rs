@@ -0,0 +1,223 @@
+use crate::{parse_signing_key, parse_verifying_key, read_key_file};
+use anyhow::Result;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ed25519_dalek::VerifyingKey;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+    EllipticCurveKeyType, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType,
+    PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::traits::PublicKeyParts;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fs;
+
+/// RFC 7638 thumbprint of a key: the SHA-256 of its required members in
+/// lexicographic order, base64url encoded.
+pub fn jwk_thumbprint(jwk: &Jwk) -> Result<String> {
+    let members = match &jwk.algorithm {
+        AlgorithmParameters::RSA(params) => json!({"e": params.e, "kty": "RSA", "n": params.n}),
+        AlgorithmParameters::EllipticCurve(params) => json!({
+            "crv": params.curve,
+            "kty": "EC",
+            "x": params.x,
+            "y": params.y,
+        }),
+        AlgorithmParameters::OctetKeyPair(params) => {
+            json!({"crv": params.curve, "kty": "OKP", "x": params.x})
+        }
+        AlgorithmParameters::OctetKey(params) => json!({"k": params.value, "kty": "oct"}),
+    };
+    // serde_json keeps object keys sorted, as the thumbprint requires
+    let digest = Sha256::digest(serde_json::to_vec(&members)?);
+    Ok(URL_SAFE_NO_PAD.encode(digest))
+}
+
+fn rsa_params(key: &RsaPublicKey) -> AlgorithmParameters {
+    AlgorithmParameters::RSA(RSAKeyParameters {
+        key_type: RSAKeyType::RSA,
+        n: URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+        e: URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+    })
+}
+
+fn ec_params(curve: EllipticCurve, point: &[u8]) -> Result<AlgorithmParameters> {
+    // uncompressed SEC1 point: 0x04 || x || y
+    let coords = point
+        .strip_prefix(&[0x04])
+        .ok_or_else(|| anyhow::anyhow!("Expected an uncompressed EC point"))?;
+    let (x, y) = coords.split_at(coords.len() / 2);
+    Ok(AlgorithmParameters::EllipticCurve(
+        EllipticCurveKeyParameters {
+            key_type: EllipticCurveKeyType::EC,
+            curve,
+            x: URL_SAFE_NO_PAD.encode(x),
+            y: URL_SAFE_NO_PAD.encode(y),
+        },
+    ))
+}
+
+fn ed25519_params(key: &VerifyingKey) -> AlgorithmParameters {
+    AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+        key_type: OctetKeyPairType::OctetKeyPair,
+        curve: EllipticCurve::Ed25519,
+        x: URL_SAFE_NO_PAD.encode(key.as_bytes()),
+    })
+}
+
+fn rsa_public_key(der: &[u8]) -> Option<RsaPublicKey> {
+    RsaPublicKey::from_public_key_der(der)
+        .or_else(|_| RsaPublicKey::from_pkcs1_der(der))
+        .ok()
+        .or_else(|| {
+            RsaPrivateKey::from_pkcs8_der(der)
+                .or_else(|_| RsaPrivateKey::from_pkcs1_der(der))
+                .ok()
+                .map(|key| key.to_public_key())
+        })
+}
+
+fn p256_point(der: &[u8]) -> Option<Vec<u8>> {
+    p256::PublicKey::from_public_key_der(der)
+        .ok()
+        .or_else(|| Some(p256::SecretKey::from_pkcs8_der(der).ok()?.public_key()))
+        .map(|key| key.to_encoded_point(false).as_bytes().to_vec())
+}
+
+fn p384_point(der: &[u8]) -> Option<Vec<u8>> {
+    p384::PublicKey::from_public_key_der(der)
+        .ok()
+        .or_else(|| Some(p384::SecretKey::from_pkcs8_der(der).ok()?.public_key()))
+        .map(|key| key.to_encoded_point(false).as_bytes().to_vec())
+}
+
+/// The public JWK of an RSA, P-256, P-384 or Ed25519 key given as PEM or DER,
+/// public or private, with its thumbprint as `kid`. Ed25519 keys may be in
+/// any format `text sign` reads.
+pub fn public_jwk(data: &[u8]) -> Result<Jwk> {
+    let der = match std::str::from_utf8(data).map(str::trim) {
+        Ok(text) if text.starts_with("-----BEGIN ") => pem_rfc7468::decode_vec(text.as_bytes())
+            .map(|(_, der)| der)
+            .unwrap_or_default(),
+        _ => data.to_vec(),
+    };
+    let (algorithm, key_algorithm) = if let Some(key) = rsa_public_key(&der) {
+        (rsa_params(&key), None)
+    } else if let Some(point) = p256_point(&der) {
+        (
+            ec_params(EllipticCurve::P256, &point)?,
+            Some(KeyAlgorithm::ES256),
+        )
+    } else if let Some(point) = p384_point(&der) {
+        (
+            ec_params(EllipticCurve::P384, &point)?,
+            Some(KeyAlgorithm::ES384),
+        )
+    } else {
+        let key = parse_verifying_key(data)
+            .or_else(|_| parse_signing_key(data).map(|key| key.verifying_key()))
+            .map_err(|_| {
+                anyhow::anyhow!("Unsupported key, expected RSA, P-256, P-384 or Ed25519")
+            })?;
+        (ed25519_params(&key), Some(KeyAlgorithm::EdDSA))
+    };
+    let mut jwk = Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm,
+            ..Default::default()
+        },
+        algorithm,
+    };
+    jwk.common.key_id = Some(jwk_thumbprint(&jwk)?);
+    Ok(jwk)
+}
+
+pub fn read_jwks(path: &str) -> Result<JwkSet> {
+    serde_json::from_slice(&fs::read(path)?)
+        .map_err(|e| anyhow::anyhow!("{} is not a JWK Set: {}", path, e))
+}
+
+/// Pick the key for a token: the one matching its `kid`, or the only key of
+/// the set when the token has no `kid`.
+pub(crate) fn select_jwk<'a>(set: &'a JwkSet, kid: Option<&str>) -> Result<&'a Jwk> {
+    match kid {
+        Some(kid) => set
+            .find(kid)
+            .ok_or_else(|| anyhow::anyhow!("No key with kid {} in the JWK Set", kid)),
+        None if set.keys.len() == 1 => Ok(&set.keys[0]),
+        None => Err(anyhow::anyhow!(
+            "Token has no kid and the JWK Set holds {} keys",
+            set.keys.len()
+        )),
+    }
+}
+
+/// Build a JWK Set from public or private key files. Only the public halves
+/// are exported.
+pub async fn process_jwks(keys: &[String]) -> Result<JwkSet> {
+    let keys = keys
+        .iter()
+        .map(|path| {
+            public_jwk(&read_key_file(path, None)?).map_err(|e| anyhow::anyhow!("{}: {}", path, e))
+        })
+        .collect::<Result<_>>()?;
+    Ok(JwkSet { keys })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jwk_thumbprint() -> Result<()> {
+        // RFC 7638, section 3.1
+        let jwk: Jwk = serde_json::from_value(json!({
+            "kty": "RSA",
+            "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+            "e": "AQAB",
+            "alg": "RS256",
+            "kid": "2011-04-29"
+        }))?;
+        assert_eq!(
+            jwk_thumbprint(&jwk)?,
+            "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_jwks() -> Result<()> {
+        let keys = [
+            "fixture/jwt_rsa.pub.pem",
+            "fixture/jwt_rsa.pem",
+            "fixture/jwt_ec.der",
+            "fixture/jwt_ed25519.pem",
+        ]
+        .map(String::from);
+        let set = process_jwks(&keys).await?;
+        let kids: Vec<_> = set.keys.iter().map(|k| k.common.key_id.clone()).collect();
+        // the private key exports the same public JWK as its public half
+        assert_eq!(kids[0], kids[1]);
+        assert!(matches!(
+            set.keys[2].algorithm,
+            AlgorithmParameters::EllipticCurve(_)
+        ));
+        assert!(matches!(
+            set.keys[3].algorithm,
+            AlgorithmParameters::OctetKeyPair(_)
+        ));
+
+        let kid = kids[2].as_deref();
+        assert!(select_jwk(&set, kid)?.common.key_id.as_deref() == kid);
+        assert!(select_jwk(&set, Some("missing")).is_err());
+        assert!(select_jwk(&set, None).is_err());
+        assert!(process_jwks(&["Cargo.toml".into()]).await.is_err());
+        Ok(())
+    }
+}
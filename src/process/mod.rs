@@ -1,13 +1,31 @@
+mod age_process;
 mod base64_process;
 mod csv_convert;
+mod envelope;
 mod gen_password;
+mod hash_process;
 mod http_serve;
+mod jwe_process;
+mod jwk_process;
+mod key_format;
+mod oidc_serve;
+mod paseto_process;
 mod process_jwt;
 mod text_process;
+mod token_claims;
 
+pub use age_process::*;
 pub use base64_process::*;
 pub use csv_convert::*;
+pub(crate) use envelope::*;
 pub use gen_password::*;
+pub use hash_process::*;
 pub use http_serve::*;
+pub use jwe_process::*;
+pub use jwk_process::*;
+pub use key_format::*;
+pub use oidc_serve::*;
+pub use paseto_process::*;
 pub use process_jwt::*;
 pub use text_process::*;
+pub use token_claims::*;
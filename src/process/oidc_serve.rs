@@ -0,0 +1,444 @@
+use crate::{process_jwks, process_jwt_sign, Claims, JwtAlg, JwtKey};
+use anyhow::Result;
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Form, Json, Router,
+};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use jsonwebtoken::jwk::{JwkSet, KeyAlgorithm};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::{collections::HashMap, sync::Arc};
+use tracing::info;
+
+/// Settings of the mock identity provider. Empty `clients` or `users` accept
+/// any credentials for that side.
+#[derive(Debug)]
+pub struct OidcConfig {
+    /// Issuer URL without a trailing `/`.
+    pub issuer: String,
+    pub alg: JwtAlg,
+    pub key: JwtKey,
+    pub kid: Option<String>,
+    /// Lifetime of issued tokens in seconds.
+    pub ttl: u64,
+    /// Audience of access tokens unless the request names one.
+    pub aud: Option<String>,
+    /// Extra claims added to every token.
+    pub claims: Map<String, Value>,
+    pub clients: HashMap<String, String>,
+    pub users: HashMap<String, String>,
+}
+
+#[derive(Debug)]
+struct OidcState {
+    config: OidcConfig,
+    jwks: JwkSet,
+}
+
+/// Form of a token request, RFC 6749 section 4.3 and 4.4.
+#[derive(Debug, Default, Deserialize)]
+struct TokenRequest {
+    grant_type: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    scope: Option<String>,
+    audience: Option<String>,
+}
+
+/// Serve discovery, the JWK Set and a token endpoint on `host:port`.
+pub async fn process_oidc_serve(config: OidcConfig, host: &str, port: u16) -> Result<()> {
+    let issuer = config.issuer.clone();
+    let app = oidc_router(config).await?;
+    let listener = tokio::net::TcpListener::bind((host, port)).await?;
+    info!("Issuing tokens as {} on {}:{}", issuer, host, port);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn oidc_router(config: OidcConfig) -> Result<Router> {
+    let state = Arc::new(oidc_state(config).await?);
+    Ok(Router::new()
+        .route("/.well-known/openid-configuration", get(discovery_handler))
+        .route("/jwks.json", get(jwks_handler))
+        .route("/token", post(token_handler))
+        .with_state(state))
+}
+
+async fn oidc_state(config: OidcConfig) -> Result<OidcState> {
+    // sign once so a key that does not fit the algorithm fails at startup
+    process_jwt_sign(Claims::default(), config.alg, &config.key, None).await?;
+    // HMAC secrets are never published, so a secret gives an empty set
+    let mut jwks = match &config.key {
+        JwtKey::File(path) => process_jwks(std::slice::from_ref(path)).await?,
+        _ => JwkSet { keys: Vec::new() },
+    };
+    let alg: KeyAlgorithm = config.alg.to_string().parse()?;
+    for jwk in &mut jwks.keys {
+        jwk.common.key_algorithm = Some(alg);
+        if config.kid.is_some() {
+            jwk.common.key_id.clone_from(&config.kid);
+        }
+    }
+    Ok(OidcState { config, jwks })
+}
+
+/// Provider metadata. Only the token endpoint exists: there is no
+/// authorization endpoint, so no response types are advertised and clients
+/// relying on browser flows (code, implicit) cannot use this server.
+async fn discovery_handler(State(state): State<Arc<OidcState>>) -> Json<Value> {
+    let issuer = &state.config.issuer;
+    Json(json!({
+        "issuer": issuer,
+        "token_endpoint": format!("{}/token", issuer),
+        "jwks_uri": format!("{}/jwks.json", issuer),
+        "grant_types_supported": ["client_credentials", "password"],
+        "token_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": [state.config.alg.to_string()],
+    }))
+}
+
+async fn jwks_handler(State(state): State<Arc<OidcState>>) -> Json<JwkSet> {
+    Json(state.jwks.clone())
+}
+
+/// An RFC 6749 error response.
+fn oauth_error(status: StatusCode, error: &str, description: &str) -> Response {
+    let body = Json(json!({"error": error, "error_description": description}));
+    if status == StatusCode::UNAUTHORIZED {
+        (status, [(header::WWW_AUTHENTICATE, "Basic")], body).into_response()
+    } else {
+        (status, body).into_response()
+    }
+}
+
+/// Client credentials from HTTP Basic authentication or, failing that, from
+/// the form.
+fn client_credentials(headers: &HeaderMap, req: &TokenRequest) -> Option<(String, String)> {
+    let basic = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok());
+    if let Some((id, secret)) = basic.as_deref().and_then(|s| s.split_once(':')) {
+        return Some((id.into(), secret.into()));
+    }
+    Some((
+        req.client_id.clone()?,
+        req.client_secret.clone().unwrap_or_default(),
+    ))
+}
+
+/// Whether `name` and `secret` match, any pair passing when none are configured.
+fn check_credentials(known: &HashMap<String, String>, name: &str, secret: &str) -> bool {
+    known.is_empty() || known.get(name).is_some_and(|s| s == secret)
+}
+
+async fn token_handler(
+    State(state): State<Arc<OidcState>>,
+    headers: HeaderMap,
+    Form(req): Form<TokenRequest>,
+) -> Response {
+    let config = &state.config;
+    let Some((client_id, client_secret)) = client_credentials(&headers, &req) else {
+        return oauth_error(
+            StatusCode::UNAUTHORIZED,
+            "invalid_client",
+            "Client authentication is required",
+        );
+    };
+    if !check_credentials(&config.clients, &client_id, &client_secret) {
+        return oauth_error(
+            StatusCode::UNAUTHORIZED,
+            "invalid_client",
+            "Unknown client or wrong secret",
+        );
+    }
+    let subject = match req.grant_type.as_deref() {
+        Some("client_credentials") => client_id.clone(),
+        Some("password") => {
+            let (Some(username), Some(password)) = (&req.username, &req.password) else {
+                return oauth_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_request",
+                    "The password grant requires username and password",
+                );
+            };
+            if !check_credentials(&config.users, username, password) {
+                return oauth_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_grant",
+                    "Unknown user or wrong password",
+                );
+            }
+            username.clone()
+        }
+        Some(grant) => {
+            return oauth_error(
+                StatusCode::BAD_REQUEST,
+                "unsupported_grant_type",
+                &format!("Grant type {} is not supported", grant),
+            )
+        }
+        None => {
+            return oauth_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_request",
+                "grant_type is required",
+            )
+        }
+    };
+    match issue_tokens(config, &req, client_id, subject).await {
+        Ok(body) => (
+            [(header::CACHE_CONTROL, "no-store")],
+            Json(Value::Object(body)),
+        )
+            .into_response(),
+        Err(e) => oauth_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "server_error",
+            &e.to_string(),
+        ),
+    }
+}
+
+/// Mint the access token and, for the password grant with the `openid`
+/// scope, an ID token for the client.
+async fn issue_tokens(
+    config: &OidcConfig,
+    req: &TokenRequest,
+    client_id: String,
+    subject: String,
+) -> Result<Map<String, Value>> {
+    let exp = jsonwebtoken::get_current_timestamp() + config.ttl;
+    let claims = |aud: Option<String>, extra: &[(&str, &Option<String>)]| {
+        let mut custom = config.claims.clone();
+        for (name, value) in extra {
+            if let Some(value) = value {
+                custom.insert(name.to_string(), value.clone().into());
+            }
+        }
+        Claims {
+            sub: Some(subject.clone()),
+            aud,
+            iss: Some(config.issuer.clone()),
+            exp: Some(exp),
+            custom,
+            ..Default::default()
+        }
+    };
+    let aud = req.audience.clone().or_else(|| config.aud.clone());
+    let access = claims(
+        aud,
+        &[
+            ("client_id", &Some(client_id.clone())),
+            ("scope", &req.scope),
+        ],
+    );
+    let access_token =
+        process_jwt_sign(access, config.alg, &config.key, config.kid.clone()).await?;
+
+    let mut body = Map::new();
+    body.insert("access_token".into(), access_token.into());
+    body.insert("token_type".into(), "Bearer".into());
+    body.insert("expires_in".into(), config.ttl.into());
+    if let Some(scope) = &req.scope {
+        body.insert("scope".into(), scope.clone().into());
+    }
+    let openid = req
+        .scope
+        .as_deref()
+        .is_some_and(|scope| scope.split(' ').any(|s| s == "openid"));
+    if openid && req.grant_type.as_deref() == Some("password") {
+        let id = claims(Some(client_id), &[]);
+        let id_token = process_jwt_sign(id, config.alg, &config.key, config.kid.clone()).await?;
+        body.insert("id_token".into(), id_token.into());
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{process_jwt_decode, process_jwt_verify, JwtValidation};
+
+    fn config(key: JwtKey, alg: JwtAlg) -> OidcConfig {
+        OidcConfig {
+            issuer: "http://localhost:8080".into(),
+            alg,
+            key,
+            kid: None,
+            ttl: 300,
+            aud: Some("api".into()),
+            claims: Map::from_iter([("tenant".to_string(), json!("acme"))]),
+            clients: HashMap::from([("svc".into(), "s3cret".into())]),
+            users: HashMap::from([("alice".into(), "pw".into())]),
+        }
+    }
+
+    async fn token(
+        state: &Arc<OidcState>,
+        headers: HeaderMap,
+        req: TokenRequest,
+    ) -> (StatusCode, Value) {
+        let res = token_handler(State(state.clone()), headers, Form(req)).await;
+        let status = res.status();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_oidc_token_grants() -> Result<()> {
+        let key = JwtKey::Secret("oidcsecret".parse()?);
+        let state = Arc::new(oidc_state(config(key.clone(), JwtAlg::Hs256)).await?);
+        assert!(state.jwks.keys.is_empty());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Basic {}", STANDARD.encode("svc:s3cret")).parse()?,
+        );
+        let req = TokenRequest {
+            grant_type: Some("client_credentials".into()),
+            scope: Some("read".into()),
+            ..Default::default()
+        };
+        let (status, body) = token(&state, headers, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.get("id_token").is_none());
+        let validation = JwtValidation {
+            aud: vec!["api".into()],
+            iss: vec!["http://localhost:8080".into()],
+            ..Default::default()
+        };
+        let report =
+            process_jwt_verify(body["access_token"].as_str().unwrap(), &validation, &key).await?;
+        assert!(report.valid);
+        let claims = report.claims.unwrap();
+        assert_eq!(claims["sub"], "svc");
+        assert_eq!(claims["scope"], "read");
+        assert_eq!(claims["tenant"], "acme");
+
+        let req = TokenRequest {
+            grant_type: Some("password".into()),
+            client_id: Some("svc".into()),
+            client_secret: Some("s3cret".into()),
+            username: Some("alice".into()),
+            password: Some("pw".into()),
+            scope: Some("openid profile".into()),
+            ..Default::default()
+        };
+        let (status, body) = token(&state, HeaderMap::new(), req).await;
+        assert_eq!(status, StatusCode::OK);
+        let id_token = process_jwt_decode(body["id_token"].as_str().unwrap())?;
+        assert_eq!(id_token.payload["sub"], "alice");
+        assert_eq!(id_token.payload["aud"], "svc");
+
+        let base = || TokenRequest {
+            grant_type: Some("client_credentials".into()),
+            client_id: Some("svc".into()),
+            client_secret: Some("s3cret".into()),
+            ..Default::default()
+        };
+        let cases = [
+            (
+                TokenRequest {
+                    client_secret: Some("wrong".into()),
+                    ..base()
+                },
+                StatusCode::UNAUTHORIZED,
+                "invalid_client",
+            ),
+            (
+                TokenRequest {
+                    grant_type: Some("password".into()),
+                    username: Some("alice".into()),
+                    password: Some("nope".into()),
+                    ..base()
+                },
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+            ),
+            (
+                TokenRequest {
+                    grant_type: Some("implicit".into()),
+                    ..base()
+                },
+                StatusCode::BAD_REQUEST,
+                "unsupported_grant_type",
+            ),
+            (
+                TokenRequest {
+                    grant_type: None,
+                    ..base()
+                },
+                StatusCode::BAD_REQUEST,
+                "invalid_request",
+            ),
+        ];
+        for (req, expected, error) in cases {
+            let (status, body) = token(&state, HeaderMap::new(), req).await;
+            assert_eq!(status, expected);
+            assert_eq!(body["error"], error);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_oidc_discovery_and_jwks() -> Result<()> {
+        let key = JwtKey::File("fixture/jwt_rsa.pem".into());
+        let state = Arc::new(oidc_state(config(key, JwtAlg::Rs256)).await?);
+        let Json(discovery) = discovery_handler(State(state.clone())).await;
+        assert_eq!(discovery["jwks_uri"], "http://localhost:8080/jwks.json");
+        assert_eq!(
+            discovery["id_token_signing_alg_values_supported"][0],
+            "RS256"
+        );
+        assert!(discovery.get("authorization_endpoint").is_none());
+        assert!(discovery.get("response_types_supported").is_none());
+
+        let Json(jwks) = jwks_handler(State(state.clone())).await;
+        assert_eq!(jwks.keys.len(), 1);
+        assert_eq!(jwks.keys[0].common.key_algorithm, Some(KeyAlgorithm::RS256));
+
+        // tokens verify against the published set
+        let req = TokenRequest {
+            grant_type: Some("client_credentials".into()),
+            client_id: Some("svc".into()),
+            client_secret: Some("s3cret".into()),
+            ..Default::default()
+        };
+        let (_, body) = token(&state, HeaderMap::new(), req).await;
+        let dir = tempfile::tempdir()?;
+        let jwks_file = dir.path().join("oidc_jwks.json");
+        std::fs::write(&jwks_file, serde_json::to_vec(&jwks)?)?;
+        let validation = JwtValidation {
+            algs: vec![JwtAlg::Rs256],
+            aud: vec!["api".into()],
+            ..Default::default()
+        };
+        let jwks_key = JwtKey::Jwks(jwks_file.to_str().unwrap().into());
+        let report = process_jwt_verify(
+            body["access_token"].as_str().unwrap(),
+            &validation,
+            &jwks_key,
+        )
+        .await?;
+        assert!(report.valid);
+
+        // a secret does not fit RS256
+        let secret = JwtKey::Secret("oidcsecret".parse()?);
+        assert!(oidc_state(config(secret, JwtAlg::Rs256)).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,586 @@
+use crate::{
+    decode_key, ed25519_fingerprint, parse_signing_key, parse_verifying_key, read_key_file, Claims,
+    PasetoPurpose, TimeStatus,
+};
+use anyhow::Result;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use blake2::digest::consts::{U32, U56};
+use blake2::digest::Mac;
+use blake2::Blake2bMac;
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::XChaCha20;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::{rngs::OsRng, RngCore};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use zeroize::Zeroizing;
+
+const NONCE_LEN: usize = 32;
+const TAG_LEN: usize = 32;
+const SIGNATURE_LEN: usize = 64;
+
+/// The `v4.local.` or `v4.public.` header of a token.
+fn header(purpose: PasetoPurpose) -> String {
+    format!("v4.{}.", purpose)
+}
+
+/// Pre-authentication encoding: the piece count, then each piece prefixed
+/// with its length, all as 64-bit little-endian.
+fn pae(pieces: &[&[u8]]) -> Vec<u8> {
+    let mut out = (pieces.len() as u64).to_le_bytes().to_vec();
+    for piece in pieces {
+        out.extend((piece.len() as u64).to_le_bytes());
+        out.extend(*piece);
+    }
+    out
+}
+
+fn encode_token(purpose: PasetoPurpose, body: &[u8], footer: &[u8]) -> String {
+    let mut token = header(purpose) + &URL_SAFE_NO_PAD.encode(body);
+    if !footer.is_empty() {
+        token.push('.');
+        token.push_str(&URL_SAFE_NO_PAD.encode(footer));
+    }
+    token
+}
+
+/// A token split into its parts, nothing checked but the encoding.
+struct PasetoParts {
+    purpose: PasetoPurpose,
+    body: Vec<u8>,
+    footer: Vec<u8>,
+}
+
+fn split_token(token: &str) -> Result<PasetoParts> {
+    let parts: Vec<_> = token.split('.').collect();
+    if !(3..=4).contains(&parts.len()) {
+        return Err(anyhow::anyhow!(
+            "Expected a token of three or four dot-separated parts, got {}",
+            parts.len()
+        ));
+    }
+    if parts[0] != "v4" {
+        return Err(anyhow::anyhow!(
+            "Unsupported PASETO version {}, expected v4",
+            parts[0]
+        ));
+    }
+    let purpose = match parts[1] {
+        "local" => PasetoPurpose::Local,
+        "public" => PasetoPurpose::Public,
+        purpose => return Err(anyhow::anyhow!("Unknown PASETO purpose {}", purpose)),
+    };
+    let decode = |part: &str, name| {
+        URL_SAFE_NO_PAD
+            .decode(part)
+            .map_err(|e| anyhow::anyhow!("Invalid token {}: {}", name, e))
+    };
+    let footer = match parts.get(3) {
+        Some(&"") => return Err(anyhow::anyhow!("Empty token footer")),
+        Some(footer) => decode(footer, "footer")?,
+        None => Vec::new(),
+    };
+    Ok(PasetoParts {
+        purpose,
+        body: decode(parts[2], "body")?,
+        footer,
+    })
+}
+
+/// The XChaCha20 cipher and the BLAKE2b MAC for a v4.local nonce, both
+/// derived from the key.
+fn local_keys(key: &[u8; 32], nonce: &[u8]) -> Result<(XChaCha20, Blake2bMac<U32>)> {
+    let derive = |info: &[u8]| {
+        Blake2bMac::<U56>::new_from_slice(key)
+            .map(|mac| mac.chain_update(info).chain_update(nonce))
+            .map_err(|_| anyhow::anyhow!("Invalid key length"))
+    };
+    let tmp = Zeroizing::new(derive(b"paseto-encryption-key")?.finalize().into_bytes());
+    let (encryption_key, counter_nonce) = tmp.split_at(32);
+    let cipher = XChaCha20::new(encryption_key.into(), counter_nonce.into());
+    let auth_key = Zeroizing::new(
+        Blake2bMac::<U32>::new_from_slice(key)
+            .map_err(|_| anyhow::anyhow!("Invalid key length"))?
+            .chain_update(b"paseto-auth-key-for-aead")
+            .chain_update(nonce)
+            .finalize()
+            .into_bytes(),
+    );
+    let mac = Blake2bMac::<U32>::new_from_slice(&auth_key)
+        .map_err(|_| anyhow::anyhow!("Invalid key length"))?;
+    Ok((cipher, mac))
+}
+
+fn local_encrypt(
+    key: &[u8; 32],
+    nonce: &[u8; NONCE_LEN],
+    message: &[u8],
+    footer: &[u8],
+    implicit: &[u8],
+) -> Result<String> {
+    let (mut cipher, mac) = local_keys(key, nonce)?;
+    let mut ciphertext = message.to_vec();
+    cipher.apply_keystream(&mut ciphertext);
+    let header = header(PasetoPurpose::Local);
+    let pre_auth = pae(&[header.as_bytes(), nonce, &ciphertext, footer, implicit]);
+    let tag = mac.chain_update(pre_auth).finalize().into_bytes();
+    let body = [nonce.as_slice(), &ciphertext, &tag].concat();
+    Ok(encode_token(PasetoPurpose::Local, &body, footer))
+}
+
+fn local_decrypt(key: &[u8; 32], body: &[u8], footer: &[u8], implicit: &[u8]) -> Result<Vec<u8>> {
+    if body.len() < NONCE_LEN + TAG_LEN {
+        return Err(anyhow::anyhow!("Token is too short"));
+    }
+    let (nonce, rest) = body.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+    let (mut cipher, mac) = local_keys(key, nonce)?;
+    let header = header(PasetoPurpose::Local);
+    // `verify_slice` compares in constant time
+    mac.chain_update(pae(&[
+        header.as_bytes(),
+        nonce,
+        ciphertext,
+        footer,
+        implicit,
+    ]))
+    .verify_slice(tag)
+    .map_err(|_| anyhow::anyhow!("Invalid token tag, wrong key or tampered token"))?;
+    let mut message = ciphertext.to_vec();
+    cipher.apply_keystream(&mut message);
+    Ok(message)
+}
+
+fn public_sign(key: &SigningKey, message: &[u8], footer: &[u8], implicit: &[u8]) -> String {
+    let header = header(PasetoPurpose::Public);
+    let signature = key.sign(&pae(&[header.as_bytes(), message, footer, implicit]));
+    let body = [message, &signature.to_bytes()].concat();
+    encode_token(PasetoPurpose::Public, &body, footer)
+}
+
+fn public_verify(
+    key: &VerifyingKey,
+    body: &[u8],
+    footer: &[u8],
+    implicit: &[u8],
+) -> Result<Vec<u8>> {
+    if body.len() < SIGNATURE_LEN {
+        return Err(anyhow::anyhow!("Token is too short"));
+    }
+    let (message, signature) = body.split_at(body.len() - SIGNATURE_LEN);
+    let header = header(PasetoPurpose::Public);
+    key.verify(
+        &pae(&[header.as_bytes(), message, footer, implicit]),
+        &Signature::from_slice(signature)?,
+    )
+    .map_err(|_| anyhow::anyhow!("Invalid token signature"))?;
+    Ok(message.to_vec())
+}
+
+fn read_local_key(path: &str) -> Result<Zeroizing<[u8; 32]>> {
+    Ok(Zeroizing::new(decode_key(&read_key_file(path, None)?)?))
+}
+
+/// Claims as a PASETO payload, where `exp`, `nbf` and `iat` are RFC 3339
+/// date times rather than Unix timestamps.
+fn paseto_claims(claims: Claims) -> Result<Map<String, Value>> {
+    let mut claims = claims.into_map();
+    for name in ["exp", "nbf", "iat"] {
+        if let Some(timestamp) = claims.get(name).and_then(Value::as_i64) {
+            let time = OffsetDateTime::from_unix_timestamp(timestamp)?.format(&Rfc3339)?;
+            claims.insert(name.into(), time.into());
+        }
+    }
+    Ok(claims)
+}
+
+/// Sign (`public`, with an Ed25519 private key) or encrypt (`local`, with a
+/// 32-byte key) a token. The implicit assertion is authenticated but not
+/// stored in the token.
+pub async fn process_paseto_sign(
+    claims: Claims,
+    purpose: PasetoPurpose,
+    key: &str,
+    footer: &str,
+    implicit: &str,
+) -> Result<String> {
+    let message = serde_json::to_vec(&paseto_claims(claims)?)?;
+    let (footer, implicit) = (footer.as_bytes(), implicit.as_bytes());
+    match purpose {
+        PasetoPurpose::Local => {
+            let mut nonce = [0u8; NONCE_LEN];
+            OsRng.fill_bytes(&mut nonce);
+            local_encrypt(&*read_local_key(key)?, &nonce, &message, footer, implicit)
+        }
+        PasetoPurpose::Public => {
+            let key = parse_signing_key(&read_key_file(key, None)?)?;
+            Ok(public_sign(&key, &message, footer, implicit))
+        }
+    }
+}
+
+/// What a token must satisfy besides a valid tag or signature.
+#[derive(Debug, Clone)]
+pub struct PasetoValidation {
+    pub aud: Option<String>,
+    pub iss: Option<String>,
+    pub sub: Option<String>,
+    /// Seconds of clock skew tolerated for `exp` and `nbf`.
+    pub leeway: u64,
+    pub ignore_exp: bool,
+    /// Footer the token must carry; any footer is accepted when unset.
+    pub footer: Option<String>,
+    pub implicit: String,
+}
+
+impl Default for PasetoValidation {
+    fn default() -> Self {
+        Self {
+            aud: None,
+            iss: None,
+            sub: None,
+            leeway: 60,
+            ignore_exp: false,
+            footer: None,
+            implicit: String::new(),
+        }
+    }
+}
+
+/// The `exp`, `iat` and `nbf` claims that are present, as Unix timestamps.
+fn claim_times(claims: &Value) -> Result<Vec<(&'static str, i64)>> {
+    ["exp", "iat", "nbf"]
+        .into_iter()
+        .filter_map(|name| Some((name, claims.get(name)?)))
+        .map(|(name, value)| {
+            let time = value
+                .as_str()
+                .and_then(|time| OffsetDateTime::parse(time, &Rfc3339).ok())
+                .ok_or_else(|| anyhow::anyhow!("Claim {} must be an RFC 3339 date time", name))?;
+            Ok((name, time.unix_timestamp()))
+        })
+        .collect()
+}
+
+fn check_claims(claims: &Value, validation: &PasetoValidation, now: i64) -> Result<()> {
+    if !claims.is_object() {
+        return Err(anyhow::anyhow!("Token payload is not a JSON object"));
+    }
+    let times = claim_times(claims)?;
+    let time = |name| times.iter().find(|(n, _)| *n == name).map(|(_, t)| *t);
+    let leeway = validation.leeway as i64;
+    match time("exp") {
+        _ if validation.ignore_exp => {}
+        Some(exp) if exp + leeway < now => return Err(anyhow::anyhow!("Token has expired")),
+        Some(_) => {}
+        None => return Err(anyhow::anyhow!("Token has no exp claim")),
+    }
+    if time("nbf").is_some_and(|nbf| nbf > now + leeway) {
+        return Err(anyhow::anyhow!("Token is not yet valid"));
+    }
+    let expected = [
+        ("aud", &validation.aud),
+        ("iss", &validation.iss),
+        ("sub", &validation.sub),
+    ];
+    for (name, expected) in expected {
+        if let Some(expected) = expected {
+            if claims.get(name).and_then(Value::as_str) != Some(expected) {
+                return Err(anyhow::anyhow!("Claim {} does not match", name));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Outcome of a token check, as printed by `paseto verify --json`.
+#[derive(Debug, Serialize)]
+pub struct PasetoVerifyReport {
+    pub valid: bool,
+    pub purpose: String,
+    /// Empty for secret keys, see [`crate::VerifyReport::key_fingerprint`].
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub key_fingerprint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub claims: Option<Value>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub footer: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Verify a token of the expected purpose. As with JWTs, a token that does
+/// not validate is reported as invalid rather than returned as an error.
+pub async fn process_paseto_verify(
+    token: &str,
+    purpose: PasetoPurpose,
+    key: &str,
+    validation: &PasetoValidation,
+) -> Result<PasetoVerifyReport> {
+    let data = read_key_file(key, None)?;
+    let (local_key, public_key, key_fingerprint) = match purpose {
+        PasetoPurpose::Local => (
+            Some(Zeroizing::new(decode_key(&data)?)),
+            None,
+            String::new(),
+        ),
+        PasetoPurpose::Public => {
+            let key = parse_verifying_key(&data)?;
+            (None, Some(key), ed25519_fingerprint(&key))
+        }
+    };
+    let mut footer = String::new();
+    let res = split_token(token).and_then(|parts| {
+        // the key decides the purpose, never the token
+        if parts.purpose != purpose {
+            return Err(anyhow::anyhow!(
+                "Expected a {} token, got {}",
+                header(purpose),
+                header(parts.purpose)
+            ));
+        }
+        footer = String::from_utf8_lossy(&parts.footer).into_owned();
+        if let Some(expected) = &validation.footer {
+            if parts.footer != expected.as_bytes() {
+                return Err(anyhow::anyhow!("Token footer does not match"));
+            }
+        }
+        let implicit = validation.implicit.as_bytes();
+        let message = match (&local_key, &public_key) {
+            (Some(key), _) => local_decrypt(key, &parts.body, &parts.footer, implicit)?,
+            (_, Some(key)) => public_verify(key, &parts.body, &parts.footer, implicit)?,
+            _ => unreachable!("a key is loaded for every purpose"),
+        };
+        let claims: Value = serde_json::from_slice(&message)
+            .map_err(|e| anyhow::anyhow!("Token payload is not JSON: {}", e))?;
+        check_claims(
+            &claims,
+            validation,
+            jsonwebtoken::get_current_timestamp() as i64,
+        )?;
+        Ok(claims)
+    });
+    let mut report = PasetoVerifyReport {
+        valid: res.is_ok(),
+        purpose: header(purpose).trim_end_matches('.').into(),
+        key_fingerprint,
+        claims: None,
+        footer,
+        error: None,
+    };
+    match res {
+        Ok(claims) => report.claims = Some(claims),
+        Err(e) => report.error = Some(e.to_string()),
+    }
+    Ok(report)
+}
+
+/// A token read without its key. The payload of a local token is encrypted,
+/// so only its footer can be shown.
+#[derive(Debug)]
+pub struct PasetoDecoded {
+    pub purpose: PasetoPurpose,
+    pub payload: Option<Value>,
+    pub footer: String,
+}
+
+impl PasetoDecoded {
+    /// The `exp`, `iat` and `nbf` claims that are present and well formed, as
+    /// Unix timestamps.
+    pub fn times(&self) -> Vec<(&'static str, i64)> {
+        self.payload
+            .as_ref()
+            .and_then(|payload| claim_times(payload).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn status(&self, now: i64) -> TimeStatus {
+        let times = self.times();
+        let claim = |name| times.iter().find(|(n, _)| *n == name).map(|(_, t)| *t);
+        if claim("exp").is_some_and(|exp| exp <= now) {
+            TimeStatus::Expired
+        } else if claim("nbf").is_some_and(|nbf| nbf > now) {
+            TimeStatus::NotYetValid
+        } else {
+            TimeStatus::Valid
+        }
+    }
+}
+
+/// Decode a token without verifying it.
+pub fn process_paseto_decode(token: &str) -> Result<PasetoDecoded> {
+    let parts = split_token(token)?;
+    let payload = match parts.purpose {
+        PasetoPurpose::Local => None,
+        PasetoPurpose::Public => {
+            let len = parts.body.len().saturating_sub(SIGNATURE_LEN);
+            let payload = serde_json::from_slice(&parts.body[..len])
+                .map_err(|e| anyhow::anyhow!("Invalid token payload: {}", e))?;
+            Some(payload)
+        }
+    };
+    Ok(PasetoDecoded {
+        purpose: parts.purpose,
+        payload,
+        footer: String::from_utf8_lossy(&parts.footer).into_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &str =
+        r#"{"data":"this is a secret message","exp":"2022-01-01T00:00:00+00:00"}"#;
+    const SIGNED: &str = r#"{"data":"this is a signed message","exp":"2022-01-01T00:00:00+00:00"}"#;
+
+    #[test]
+    fn test_paseto_local_vector() -> Result<()> {
+        // 4-E-1 from the PASETO test vectors
+        let key: [u8; 32] =
+            hex::decode("707172737475767778797a7b7c7d7e7f808182838485868788898a8b8c8d8e8f")?
+                .try_into()
+                .unwrap();
+        let token = local_encrypt(&key, &[0; 32], MESSAGE.as_bytes(), b"", b"")?;
+        assert_eq!(token, "v4.local.AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAQAr68PS4AXe7If_ZgesdkUMvSwscFlAl1pk5HC0e8kApeaqMfGo_7OpBnwJOAbY9V7WU6abu74MmcUE8YWAiaArVI8XJ5hOb_4v9RmDkneN0S92dx0OW4pgy7omxgf3S8c3LlQg");
+
+        let parts = split_token(&token)?;
+        assert_eq!(
+            local_decrypt(&key, &parts.body, b"", b"")?,
+            MESSAGE.as_bytes()
+        );
+        assert!(local_decrypt(&key, &parts.body, b"", b"other").is_err());
+        assert!(local_decrypt(&[0; 32], &parts.body, b"", b"").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_paseto_public_vector() -> Result<()> {
+        // 4-S-1 from the PASETO test vectors
+        let seed: [u8; 32] =
+            hex::decode("b4cbfb43df4ce210727d953e4a713307fa19bb7d9f85041438d9e11b942a3774")?
+                .try_into()
+                .unwrap();
+        let key = SigningKey::from_bytes(&seed);
+        assert_eq!(
+            hex::encode(key.verifying_key().as_bytes()),
+            "1eb9dbbbbc047c03fd70604e0071f0987e16b28b757225c11f00415d0e20b1a2"
+        );
+        let token = public_sign(&key, SIGNED.as_bytes(), b"", b"");
+        assert_eq!(token, "v4.public.eyJkYXRhIjoidGhpcyBpcyBhIHNpZ25lZCBtZXNzYWdlIiwiZXhwIjoiMjAyMi0wMS0wMVQwMDowMDowMCswMDowMCJ9bg_XBBzds8lTZShVlwwKSgeKpLT3yukTw6JUz3W4h_ExsQV-P0V54zemZDcAxFaSeef1QlXEFtkqxT1ciiQEDA");
+
+        let parts = split_token(&token)?;
+        let public = key.verifying_key();
+        assert_eq!(
+            public_verify(&public, &parts.body, b"", b"")?,
+            SIGNED.as_bytes()
+        );
+        assert!(public_verify(&public, &parts.body, b"footer", b"").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_paseto_round_trip() -> Result<()> {
+        let cases = [
+            (
+                PasetoPurpose::Local,
+                "fixture/text_key.txt",
+                "fixture/text_key.txt",
+            ),
+            (
+                PasetoPurpose::Public,
+                "fixture/jwt_ed25519.pem",
+                "fixture/jwt_ed25519.pub.pem",
+            ),
+        ];
+        let exp = jsonwebtoken::get_current_timestamp() + 60;
+        for (purpose, private, public) in cases {
+            let claims = Claims {
+                sub: Some("acme".into()),
+                exp: Some(exp),
+                iat: Some(exp - 120),
+                ..Default::default()
+            };
+            let token = process_paseto_sign(claims, purpose, private, "kid-1", "device1").await?;
+            let decoded = process_paseto_decode(&token)?;
+            assert_eq!(decoded.purpose, purpose);
+            assert_eq!(decoded.footer, "kid-1");
+            // a local payload is encrypted, so only public tokens show their times
+            let expired = decoded.status(exp as i64 + 1) == TimeStatus::Expired;
+            assert_eq!(expired, purpose == PasetoPurpose::Public);
+
+            let mut validation = PasetoValidation {
+                sub: Some("acme".into()),
+                footer: Some("kid-1".into()),
+                implicit: "device1".into(),
+                ..Default::default()
+            };
+            let report = process_paseto_verify(&token, purpose, public, &validation).await?;
+            assert!(report.valid, "{:?}", report.error);
+            let claims = report.claims.unwrap();
+            assert_eq!(claims["exp"], format_exp(exp));
+            assert_eq!(claims["iat"], format_exp(exp - 120));
+
+            validation.implicit = "device2".into();
+            assert!(
+                !process_paseto_verify(&token, purpose, public, &validation)
+                    .await?
+                    .valid
+            );
+            validation.implicit = "device1".into();
+            validation.footer = Some("kid-2".into());
+            assert!(
+                !process_paseto_verify(&token, purpose, public, &validation)
+                    .await?
+                    .valid
+            );
+        }
+
+        // the expected purpose wins over the token's header
+        let claims = Claims::default();
+        let token =
+            process_paseto_sign(claims, PasetoPurpose::Local, "fixture/text_key.txt", "", "")
+                .await?;
+        let validation = PasetoValidation::default();
+        let report = process_paseto_verify(
+            &token,
+            PasetoPurpose::Public,
+            "fixture/jwt_ed25519.pub.pem",
+            &validation,
+        )
+        .await?;
+        assert!(!report.valid);
+        Ok(())
+    }
+
+    fn format_exp(exp: u64) -> String {
+        OffsetDateTime::from_unix_timestamp(exp as i64)
+            .unwrap()
+            .format(&Rfc3339)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_paseto_check_claims() {
+        let claims = serde_json::json!({
+            "iss": "rcli",
+            "exp": "2024-01-01T00:00:00Z",
+            "nbf": "2023-12-31T00:00:00Z",
+        });
+        let now = 1_704_067_200; // 2024-01-01T00:00:00Z
+        let validation = PasetoValidation::default();
+        assert!(check_claims(&claims, &validation, now).is_ok());
+        assert!(check_claims(&claims, &validation, now + 61).is_err());
+        assert!(check_claims(&claims, &validation, now - 2 * 86400).is_err());
+        let wrong_iss = PasetoValidation {
+            iss: Some("other".into()),
+            ..Default::default()
+        };
+        assert!(check_claims(&claims, &wrong_iss, now).is_err());
+        let no_exp = serde_json::json!({"exp": 1704067200});
+        assert!(check_claims(&no_exp, &validation, now).is_err());
+        assert!(check_claims(&serde_json::json!({}), &validation, now).is_err());
+    }
+}
@@ -1,41 +1,766 @@
+use crate::{
+    jwk_thumbprint, parse_signing_key, parse_verifying_key, public_jwk, read_jwks, read_key_file,
+    select_jwk, Claims, JwtAlg, TimeStatus,
+};
 use anyhow::Result;
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
-use serde::{Deserialize, Serialize};
-use std::io::Read;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ed25519_dalek::pkcs8::EncodePrivateKey;
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk};
+use jsonwebtoken::{decode, decode_header, encode, DecodingKey, EncodingKey, Header, Validation};
+use pem_rfc7468::LineEnding;
+use serde::Serialize;
+use serde_json::Value;
+use std::{env, fmt, fs, str::FromStr};
+use zeroize::Zeroizing;
 
-const SECRET: &str = "jwttokensecret";
+/// HMAC secret for signing and verifying tokens. `Debug` is redacted so the
+/// secret never ends up in logs or error messages.
+#[derive(Clone)]
+pub struct JwtSecret(Zeroizing<Vec<u8>>);
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    aud: String,
-    exp: u64,
+impl JwtSecret {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Result<Self> {
+        let secret = Zeroizing::new(secret.into());
+        if secret.is_empty() {
+            return Err(anyhow::anyhow!("JWT secret must not be empty"));
+        }
+        Ok(Self(secret))
+    }
+
+    /// Read the secret from a file, without its trailing newline.
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = Zeroizing::new(fs::read(path)?);
+        let len = content
+            .iter()
+            .rposition(|b| !matches!(b, b'\r' | b'\n'))
+            .map_or(0, |i| i + 1);
+        Self::new(&content[..len])
+    }
+
+    /// Read the secret from the environment variable `name`.
+    pub fn from_env(name: &str) -> Result<Self> {
+        let value = env::var(name)
+            .map_err(|_| anyhow::anyhow!("Environment variable {} is not set", name))?;
+        Self::new(value)
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for JwtSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "JwtSecret(<redacted>)")
+    }
+}
+
+impl FromStr for JwtSecret {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+/// Key material for a token: an HMAC secret for the HS algorithms, a PEM or
+/// DER key file for the asymmetric ones, or a JWK Set file to verify with.
+#[derive(Debug, Clone)]
+pub enum JwtKey {
+    Secret(JwtSecret),
+    File(String),
+    Jwks(String),
+}
+
+impl JwtKey {
+    fn secret(&self, alg: JwtAlg) -> Result<&JwtSecret> {
+        match self {
+            JwtKey::Secret(secret) => Ok(secret),
+            _ => Err(anyhow::anyhow!(
+                "{} needs a secret (--secret, --secret-file or --secret-env)",
+                alg
+            )),
+        }
+    }
+
+    fn read_file(&self, alg: JwtAlg) -> Result<Vec<u8>> {
+        match self {
+            JwtKey::File(path) => read_key_file(path, None),
+            _ => Err(anyhow::anyhow!("{} needs a key file (--key)", alg)),
+        }
+    }
+}
+
+fn is_pem(data: &[u8]) -> bool {
+    data.trim_ascii_start().starts_with(b"-----BEGIN ")
+}
+
+/// Parse an RSA or EC key with one of jsonwebtoken's PEM loaders. DER input is
+/// wrapped as PKCS#8/SPKI first and, failing that, as PKCS#1.
+fn parse_pem<K>(
+    data: &[u8],
+    private: bool,
+    parse: impl Fn(&[u8]) -> jsonwebtoken::errors::Result<K>,
+) -> Result<K> {
+    if is_pem(data) {
+        return Ok(parse(data)?);
+    }
+    let labels = if private {
+        ["PRIVATE KEY", "RSA PRIVATE KEY"]
+    } else {
+        ["PUBLIC KEY", "RSA PUBLIC KEY"]
+    };
+    let mut res = Err(anyhow::anyhow!("Invalid key"));
+    for label in labels {
+        let pem = pem_rfc7468::encode_string(label, LineEnding::LF, data)?;
+        res = parse(pem.as_bytes()).map_err(Into::into);
+        if res.is_ok() {
+            break;
+        }
+    }
+    res
+}
+
+/// `jwk:<thumbprint>` of a key file: the RFC 7638 thumbprint of its public
+/// half, the same whatever the file encoding and as in a JWK Set report.
+fn key_fingerprint(data: &[u8]) -> Result<String> {
+    Ok(format!("jwk:{}", jwk_thumbprint(&public_jwk(data)?)?))
 }
 
-pub async fn process_jwt_sign(sub: &str, aud: &str, exp: u64) -> Result<String> {
-    let claims = Claims {
-        sub: sub.to_owned(),
-        aud: aud.to_owned(),
-        exp,
+/// The key to sign with and, for key files, the thumbprint of its public
+/// half, which `jwt jwks` uses as the `kid`.
+fn encoding_key(alg: JwtAlg, key: &JwtKey) -> Result<(EncodingKey, Option<String>)> {
+    if let JwtAlg::Hs256 | JwtAlg::Hs384 | JwtAlg::Hs512 = alg {
+        return Ok((EncodingKey::from_secret(key.secret(alg)?.as_bytes()), None));
+    }
+    let data = key.read_file(alg)?;
+    let encoding_key = match alg {
+        JwtAlg::Es256 | JwtAlg::Es384 => parse_pem(&data, true, EncodingKey::from_ec_pem)?,
+        JwtAlg::EdDsa => {
+            let key = parse_signing_key(&data)?;
+            EncodingKey::from_ed_der(key.to_pkcs8_der()?.as_bytes())
+        }
+        _ => parse_pem(&data, true, EncodingKey::from_rsa_pem)?,
     };
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(SECRET.as_ref()),
-    )?;
+    Ok((encoding_key, public_jwk(&data)?.common.key_id))
+}
+
+/// The key to verify with, and its fingerprint for reports.
+fn decoding_key(alg: JwtAlg, key: &JwtKey) -> Result<(DecodingKey, String)> {
+    match alg {
+        JwtAlg::Hs256 | JwtAlg::Hs384 | JwtAlg::Hs512 => {
+            let secret = key.secret(alg)?;
+            Ok((DecodingKey::from_secret(secret.as_bytes()), String::new()))
+        }
+        JwtAlg::Rs256 | JwtAlg::Rs384 | JwtAlg::Rs512 | JwtAlg::Ps256 => {
+            let data = key.read_file(alg)?;
+            let key = parse_pem(&data, false, DecodingKey::from_rsa_pem)?;
+            Ok((key, key_fingerprint(&data)?))
+        }
+        JwtAlg::Es256 | JwtAlg::Es384 => {
+            let data = key.read_file(alg)?;
+            let key = parse_pem(&data, false, DecodingKey::from_ec_pem)?;
+            Ok((key, key_fingerprint(&data)?))
+        }
+        JwtAlg::EdDsa => {
+            let data = key.read_file(alg)?;
+            let key = parse_verifying_key(&data)?;
+            Ok((
+                DecodingKey::from_ed_der(key.as_bytes()),
+                key_fingerprint(&data)?,
+            ))
+        }
+    }
+}
+
+/// Sign a token. `kid` defaults to the key's JWK thumbprint for key files.
+pub async fn process_jwt_sign(
+    claims: Claims,
+    alg: JwtAlg,
+    key: &JwtKey,
+    kid: Option<String>,
+) -> Result<String> {
+    let (encoding_key, thumbprint) = encoding_key(alg, key)?;
+    let mut header = Header::new(alg.into());
+    header.kid = kid.or(thumbprint);
+    let token = encode(&header, &claims.into_map(), &encoding_key)?;
     Ok(token)
 }
 
-pub async fn process_jwt_verify(reader: &mut dyn Read) -> Result<String> {
-    let mut token = String::new();
-    reader.read_to_string(&mut token)?;
-    let token = token.trim().to_string();
-    let mut validation = Validation::new(Algorithm::HS256);
-    validation.validate_aud = false;
-    let token_message = decode::<Claims>(
-        &token,
-        &DecodingKey::from_secret(SECRET.as_ref()),
-        &validation,
-    )?;
-    Ok(serde_json::to_string_pretty(&token_message.claims)?)
+/// What a token must satisfy besides a valid signature.
+#[derive(Debug, Clone)]
+pub struct JwtValidation {
+    /// Accepted algorithms; with a key file they must all take the same kind
+    /// of key. When empty, a key file is taken as HS256 and a JWK Set key
+    /// decides the algorithm itself.
+    pub algs: Vec<JwtAlg>,
+    pub aud: Vec<String>,
+    pub iss: Vec<String>,
+    pub sub: Option<String>,
+    /// Seconds of clock skew tolerated for `exp` and `nbf`.
+    pub leeway: u64,
+    /// Claims that must be present, registered or custom.
+    pub require: Vec<String>,
+    pub ignore_exp: bool,
+}
+
+impl Default for JwtValidation {
+    fn default() -> Self {
+        Self {
+            algs: Vec::new(),
+            aud: Vec::new(),
+            iss: Vec::new(),
+            sub: None,
+            leeway: 60,
+            require: Vec::new(),
+            ignore_exp: false,
+        }
+    }
+}
+
+/// The kind of key an algorithm takes.
+fn key_kind(alg: JwtAlg) -> &'static str {
+    match alg {
+        JwtAlg::Hs256 | JwtAlg::Hs384 | JwtAlg::Hs512 => "HMAC secret",
+        JwtAlg::Rs256 | JwtAlg::Rs384 | JwtAlg::Rs512 | JwtAlg::Ps256 => "RSA key",
+        JwtAlg::Es256 | JwtAlg::Es384 => "EC key",
+        JwtAlg::EdDsa => "Ed25519 key",
+    }
+}
+
+/// The algorithm to verify with a JWK: its `alg` when set, otherwise the one
+/// its key type implies. RSA and oct keys fit several algorithms, so for them
+/// the token's own algorithm is taken if it is of the right kind.
+fn jwk_alg(jwk: &Jwk, token_alg: Option<JwtAlg>) -> Result<JwtAlg> {
+    if let Some(alg) = jwk.common.key_algorithm {
+        let name = serde_json::to_value(alg)?;
+        return name
+            .as_str()
+            .and_then(|name| name.parse().ok())
+            .ok_or_else(|| anyhow::anyhow!("Unsupported JWK algorithm {}", name));
+    }
+    let kind = match &jwk.algorithm {
+        AlgorithmParameters::EllipticCurve(params) => match params.curve {
+            EllipticCurve::P256 => return Ok(JwtAlg::Es256),
+            EllipticCurve::P384 => return Ok(JwtAlg::Es384),
+            _ => return Err(anyhow::anyhow!("Unsupported JWK curve")),
+        },
+        AlgorithmParameters::OctetKeyPair(params) => match params.curve {
+            EllipticCurve::Ed25519 => return Ok(JwtAlg::EdDsa),
+            _ => return Err(anyhow::anyhow!("Unsupported JWK curve")),
+        },
+        AlgorithmParameters::RSA(_) => key_kind(JwtAlg::Rs256),
+        AlgorithmParameters::OctetKey(_) => key_kind(JwtAlg::Hs256),
+    };
+    match token_alg {
+        Some(alg) if key_kind(alg) == kind => Ok(alg),
+        _ => Err(anyhow::anyhow!(
+            "The token algorithm does not fit the JWK, an {}",
+            kind
+        )),
+    }
+}
+
+impl JwtValidation {
+    /// The algorithm to verify with a key file: the first accepted one, after
+    /// checking that the others take the same kind of key so that one key can
+    /// verify them all, or HS256 when none is given.
+    fn key_alg(&self) -> Result<JwtAlg> {
+        let Some(&first) = self.algs.first() else {
+            return Ok(JwtAlg::Hs256);
+        };
+        if let Some(other) = self.algs.iter().find(|a| key_kind(**a) != key_kind(first)) {
+            return Err(anyhow::anyhow!(
+                "{} takes an {} but {} takes an {}",
+                first,
+                key_kind(first),
+                other,
+                key_kind(*other)
+            ));
+        }
+        Ok(first)
+    }
+
+    /// The checks to run, accepting only `algs`.
+    fn validation(&self, algs: &[JwtAlg]) -> Validation {
+        let mut validation = Validation::new(algs[0].into());
+        validation.algorithms = algs.iter().map(|a| (*a).into()).collect();
+        validation.leeway = self.leeway;
+        validation.validate_nbf = true;
+        validation.validate_exp = !self.ignore_exp;
+        let mut required = self.require.clone();
+        if !self.ignore_exp {
+            required.push("exp".into());
+        }
+        validation.set_required_spec_claims(&required);
+        if self.aud.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.aud);
+        }
+        if !self.iss.is_empty() {
+            validation.set_issuer(&self.iss);
+        }
+        validation.sub.clone_from(&self.sub);
+        validation
+    }
+
+    /// jsonwebtoken only enforces the registered claims in `require`.
+    fn check_required(&self, claims: &Value) -> Result<()> {
+        match self.require.iter().find(|name| claims.get(name).is_none()) {
+            Some(name) => Err(anyhow::anyhow!("Missing required claim: {}", name)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Outcome of a token check, as printed by `jwt verify --json`.
+#[derive(Debug, Serialize)]
+pub struct JwtVerifyReport {
+    pub valid: bool,
+    pub algorithm: String,
+    /// Empty for secret keys, see [`crate::VerifyReport::key_fingerprint`].
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub key_fingerprint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub claims: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Verify a token. A token that does not validate is reported as invalid
+/// rather than returned as an error.
+pub async fn process_jwt_verify(
+    token: &str,
+    validation: &JwtValidation,
+    key: &JwtKey,
+) -> Result<JwtVerifyReport> {
+    let header = decode_header(token);
+    let algorithm = match &header {
+        Ok(header) => format!("{:?}", header.alg),
+        Err(_) => "unknown".into(),
+    };
+    let keys = match key {
+        JwtKey::Jwks(path) => {
+            let set = read_jwks(path)?;
+            let kid = header.as_ref().ok().and_then(|h| h.kid.as_deref());
+            select_jwk(&set, kid).and_then(|jwk| {
+                // the key, not the token, decides the algorithm
+                let alg = jwk_alg(jwk, algorithm.parse().ok())?;
+                if !validation.algs.is_empty() && !validation.algs.contains(&alg) {
+                    return Err(anyhow::anyhow!("{} is not an accepted algorithm", alg));
+                }
+                let fingerprint = match jwk.algorithm {
+                    AlgorithmParameters::OctetKey(_) => String::new(),
+                    _ => format!("jwk:{}", jwk_thumbprint(jwk)?),
+                };
+                Ok((DecodingKey::from_jwk(jwk)?, fingerprint, vec![alg]))
+            })
+        }
+        _ => {
+            let key_alg = validation.key_alg()?;
+            let (decoding_key, fingerprint) = decoding_key(key_alg, key)?;
+            let algs = if validation.algs.is_empty() {
+                vec![key_alg]
+            } else {
+                validation.algs.clone()
+            };
+            Ok((decoding_key, fingerprint, algs))
+        }
+    };
+    let (res, key_fingerprint) = match keys {
+        Ok((decoding_key, fingerprint, algs)) => {
+            let res = decode::<Value>(token, &decoding_key, &validation.validation(&algs))
+                .map_err(anyhow::Error::from)
+                .and_then(|data| {
+                    validation.check_required(&data.claims)?;
+                    Ok(data.claims)
+                });
+            (res, fingerprint)
+        }
+        Err(e) => (Err(e), String::new()),
+    };
+    let mut report = JwtVerifyReport {
+        valid: res.is_ok(),
+        algorithm,
+        key_fingerprint,
+        claims: None,
+        error: None,
+    };
+    match res {
+        Ok(claims) => report.claims = Some(claims),
+        Err(e) => report.error = Some(e.to_string()),
+    }
+    Ok(report)
+}
+
+/// Header and payload of a token, read without checking the signature.
+#[derive(Debug)]
+pub struct JwtDecoded {
+    pub header: Value,
+    pub payload: Value,
+}
+
+impl JwtDecoded {
+    /// The `exp`, `iat` and `nbf` claims that are present, as Unix timestamps.
+    pub fn times(&self) -> Vec<(&'static str, i64)> {
+        ["exp", "iat", "nbf"]
+            .into_iter()
+            .filter_map(|name| Some((name, self.payload.get(name)?.as_i64()?)))
+            .collect()
+    }
+
+    pub fn status(&self, now: i64) -> TimeStatus {
+        let claim = |name| self.payload.get(name).and_then(Value::as_i64);
+        if claim("exp").is_some_and(|exp| exp <= now) {
+            TimeStatus::Expired
+        } else if claim("nbf").is_some_and(|nbf| nbf > now) {
+            TimeStatus::NotYetValid
+        } else {
+            TimeStatus::Valid
+        }
+    }
+}
+
+fn decode_segment(segment: &str, name: &str) -> Result<Value> {
+    let json = URL_SAFE_NO_PAD
+        .decode(segment.trim_end_matches('='))
+        .map_err(|e| anyhow::anyhow!("Invalid token {}: {}", name, e))?;
+    serde_json::from_slice(&json).map_err(|e| anyhow::anyhow!("Invalid token {}: {}", name, e))
+}
+
+/// Decode a token without verifying it, for inspecting tokens whose key is
+/// not at hand.
+pub fn process_jwt_decode(token: &str) -> Result<JwtDecoded> {
+    let parts: Vec<_> = token.split('.').collect();
+    if parts.len() != 3 {
+        return Err(anyhow::anyhow!(
+            "Expected a token of three dot-separated parts, got {}",
+            parts.len()
+        ));
+    }
+    Ok(JwtDecoded {
+        header: decode_segment(parts[0], "header")?,
+        payload: decode_segment(parts[1], "payload")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{process_jwks, read_token};
+
+    fn hs256() -> JwtValidation {
+        JwtValidation::default()
+    }
+
+    fn claims(exp: u64) -> Claims {
+        Claims {
+            sub: Some("acme".into()),
+            aud: Some("device1".into()),
+            exp: Some(exp),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_jwt_custom_claims() -> Result<()> {
+        let secret = JwtKey::Secret("jwttokensecret".parse()?);
+        let exp = jsonwebtoken::get_current_timestamp() + 60;
+        let custom = serde_json::json!({"roles": ["admin"], "tenant": 42, "sub": "overridden"});
+        let claims = Claims {
+            sub: Some("acme".into()),
+            iss: Some("rcli".into()),
+            exp: Some(exp),
+            custom: custom.as_object().unwrap().clone(),
+            ..Default::default()
+        };
+        let token = process_jwt_sign(claims, JwtAlg::Hs256, &secret, None).await?;
+        let report = process_jwt_verify(&token, &hs256(), &secret).await?;
+        let claims = report.claims.unwrap();
+        assert_eq!(claims["sub"], "acme");
+        assert_eq!(claims["iss"], "rcli");
+        assert_eq!(claims["roles"][0], "admin");
+        assert_eq!(claims["tenant"], 42);
+        assert!(claims["iat"].as_u64().unwrap() <= exp);
+        assert_eq!(claims["jti"].as_str().unwrap().len(), 32);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_jwt_verify_report() -> Result<()> {
+        let secret = JwtKey::Secret("jwttokensecret".parse()?);
+        let now = jsonwebtoken::get_current_timestamp();
+        let token = process_jwt_sign(claims(now + 60), JwtAlg::Hs256, &secret, None).await?;
+        let report = process_jwt_verify(&token, &hs256(), &secret).await?;
+        assert!(report.valid);
+        assert_eq!(report.algorithm, "HS256");
+        assert_eq!(report.claims.unwrap()["sub"], "acme");
+
+        let tampered = format!("{}x", token);
+        let report = process_jwt_verify(&tampered, &hs256(), &secret).await?;
+        assert!(!report.valid);
+        assert!(report.error.is_some());
+
+        let other = JwtKey::Secret("anothersecret".parse()?);
+        let report = process_jwt_verify(&token, &hs256(), &other).await?;
+        assert!(!report.valid);
+
+        // the algorithm is pinned by the verifier, not taken from the token
+        let hs512 = JwtValidation {
+            algs: vec![JwtAlg::Hs512],
+            ..Default::default()
+        };
+        let report = process_jwt_verify(&token, &hs512, &secret).await?;
+        assert!(!report.valid);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_jwt_validation_options() -> Result<()> {
+        let secret = JwtKey::Secret("jwttokensecret".parse()?);
+        let now = jsonwebtoken::get_current_timestamp();
+        let mut claims = claims(now + 60);
+        claims.iss = Some("rcli".into());
+        claims.custom.insert("tenant".into(), "t1".into());
+        let token = process_jwt_sign(claims, JwtAlg::Hs256, &secret, None).await?;
+        let expired =
+            process_jwt_sign(self::claims(now - 3600), JwtAlg::Hs256, &secret, None).await?;
+
+        let cases = [
+            (JwtValidation::default(), &token, true),
+            (
+                JwtValidation {
+                    aud: vec!["device1".into()],
+                    iss: vec!["rcli".into()],
+                    sub: Some("acme".into()),
+                    require: vec!["tenant".into(), "iat".into()],
+                    ..Default::default()
+                },
+                &token,
+                true,
+            ),
+            (
+                JwtValidation {
+                    aud: vec!["device2".into()],
+                    ..Default::default()
+                },
+                &token,
+                false,
+            ),
+            (
+                JwtValidation {
+                    iss: vec!["other".into()],
+                    ..Default::default()
+                },
+                &token,
+                false,
+            ),
+            (
+                JwtValidation {
+                    sub: Some("other".into()),
+                    ..Default::default()
+                },
+                &token,
+                false,
+            ),
+            (
+                JwtValidation {
+                    require: vec!["roles".into()],
+                    ..Default::default()
+                },
+                &token,
+                false,
+            ),
+            (JwtValidation::default(), &expired, false),
+            (
+                JwtValidation {
+                    leeway: 7200,
+                    ..Default::default()
+                },
+                &expired,
+                true,
+            ),
+            (
+                JwtValidation {
+                    ignore_exp: true,
+                    ..Default::default()
+                },
+                &expired,
+                true,
+            ),
+            (
+                JwtValidation {
+                    algs: vec![JwtAlg::Hs512, JwtAlg::Hs256],
+                    ..Default::default()
+                },
+                &token,
+                true,
+            ),
+        ];
+        for (i, (validation, token, valid)) in cases.iter().enumerate() {
+            let report = process_jwt_verify(token, validation, &secret).await?;
+            assert_eq!(report.valid, *valid, "case {}: {:?}", i, report.error);
+        }
+
+        let mixed = JwtValidation {
+            algs: vec![JwtAlg::Hs256, JwtAlg::Rs256],
+            ..Default::default()
+        };
+        assert!(process_jwt_verify(&token, &mixed, &secret).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_jwt_decode() -> Result<()> {
+        let secret = JwtKey::Secret("jwttokensecret".parse()?);
+        let now = jsonwebtoken::get_current_timestamp();
+        let mut claims = claims(now - 60);
+        claims.nbf = Some(now - 120);
+        let token = process_jwt_sign(claims, JwtAlg::Hs384, &secret, None).await?;
+        let token = read_token(&mut format!("  {}\n", token).as_bytes())?;
+
+        let decoded = process_jwt_decode(&token)?;
+        assert_eq!(decoded.header["alg"], "HS384");
+        assert_eq!(decoded.payload["sub"], "acme");
+        let names: Vec<_> = decoded.times().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["exp", "iat", "nbf"]);
+        let now = now as i64;
+        assert_eq!(decoded.status(now), TimeStatus::Expired);
+        assert_eq!(decoded.status(now - 90), TimeStatus::Valid);
+        assert_eq!(decoded.status(now - 180), TimeStatus::NotYetValid);
+
+        assert!(process_jwt_decode("not-a-token").is_err());
+        assert!(process_jwt_decode("e30.!!!.sig").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_jwt_asymmetric_algorithms() -> Result<()> {
+        let cases = [
+            (JwtAlg::Rs256, "jwt_rsa.pem", "jwt_rsa.pub.pem"),
+            (JwtAlg::Ps256, "jwt_rsa.pem", "jwt_rsa.pub.der"),
+            (JwtAlg::Rs512, "jwt_rsa.pem", "jwt_rsa.pub.pem"),
+            (JwtAlg::Es256, "jwt_ec.der", "jwt_ec.pub.pem"),
+            (JwtAlg::EdDsa, "jwt_ed25519.pem", "jwt_ed25519.pub.pem"),
+        ];
+        let exp = jsonwebtoken::get_current_timestamp() + 60;
+        for (alg, private, public) in cases {
+            let private = JwtKey::File(format!("fixture/{}", private));
+            let public = JwtKey::File(format!("fixture/{}", public));
+            let token = process_jwt_sign(claims(exp), alg, &private, None).await?;
+            let validation = JwtValidation {
+                algs: vec![alg],
+                ..Default::default()
+            };
+            let report = process_jwt_verify(&token, &validation, &public).await?;
+            assert!(report.valid, "{}: {:?}", alg, report.error);
+            assert_eq!(report.algorithm, alg.to_string());
+            assert!(report.key_fingerprint.starts_with("jwk:"));
+        }
+        // the fingerprint identifies the key, not the file encoding
+        let rsa = JwtKey::File("fixture/jwt_rsa.pem".into());
+        let token = process_jwt_sign(claims(exp), JwtAlg::Rs256, &rsa, None).await?;
+        let validation = JwtValidation {
+            algs: vec![JwtAlg::Rs256],
+            ..Default::default()
+        };
+        let mut fingerprints = Vec::new();
+        for name in ["jwt_rsa.pub.pem", "jwt_rsa.pub.der"] {
+            let key = JwtKey::File(format!("fixture/{}", name));
+            let report = process_jwt_verify(&token, &validation, &key).await?;
+            assert!(report.valid, "{}: {:?}", name, report.error);
+            fingerprints.push(report.key_fingerprint);
+        }
+        assert_eq!(fingerprints[0], fingerprints[1]);
+        let secret = JwtKey::Secret("jwttokensecret".parse()?);
+        assert!(process_jwt_sign(claims(exp), JwtAlg::Rs256, &secret, None)
+            .await
+            .is_err());
+        assert!(process_jwt_sign(claims(exp), JwtAlg::Hs256, &rsa, None)
+            .await
+            .is_err());
+        assert!(process_jwt_sign(claims(exp), JwtAlg::Es256, &rsa, None)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_jwt_verify_with_jwks() -> Result<()> {
+        let public = ["jwt_rsa.pub.pem", "jwt_ec.pub.pem", "jwt_ed25519.pub.pem"]
+            .map(|name| format!("fixture/{}", name));
+        let dir = tempfile::tempdir()?;
+        let jwks = dir.path().join("jwks.json");
+        fs::write(&jwks, serde_json::to_vec(&process_jwks(&public).await?)?)?;
+        let jwks = JwtKey::Jwks(jwks.to_str().unwrap().into());
+
+        let exp = jsonwebtoken::get_current_timestamp() + 60;
+        let cases = [
+            (JwtAlg::Rs256, "jwt_rsa.pem"),
+            (JwtAlg::Es256, "jwt_ec.der"),
+            (JwtAlg::EdDsa, "jwt_ed25519.pem"),
+        ];
+        for (alg, private) in cases {
+            let private = JwtKey::File(format!("fixture/{}", private));
+            let validation = JwtValidation {
+                algs: vec![alg],
+                ..Default::default()
+            };
+            let token = process_jwt_sign(claims(exp), alg, &private, None).await?;
+            let report = process_jwt_verify(&token, &validation, &jwks).await?;
+            assert!(report.valid, "{}: {:?}", alg, report.error);
+            assert!(report.key_fingerprint.starts_with("jwk:"));
+
+            let token = process_jwt_sign(claims(exp), alg, &private, Some("other".into())).await?;
+            let report = process_jwt_verify(&token, &validation, &jwks).await?;
+            assert!(!report.valid);
+        }
+
+        // without --alg every key of a mixed set verifies its own tokens
+        for (alg, private) in cases {
+            let private = JwtKey::File(format!("fixture/{}", private));
+            let token = process_jwt_sign(claims(exp), alg, &private, None).await?;
+            let report = process_jwt_verify(&token, &JwtValidation::default(), &jwks).await?;
+            assert!(report.valid, "{}: {:?}", alg, report.error);
+
+            let other = JwtValidation {
+                algs: vec![JwtAlg::Hs256, JwtAlg::Ps256],
+                ..Default::default()
+            };
+            assert!(!process_jwt_verify(&token, &other, &jwks).await?.valid);
+        }
+
+        // an oct key is a shared secret and gets no fingerprint
+        let oct = dir.path().join("oct.json");
+        let k = URL_SAFE_NO_PAD.encode("jwttokensecret");
+        let set =
+            serde_json::json!({ "keys": [{ "kty": "oct", "alg": "HS256", "kid": "hs", "k": k }] });
+        fs::write(&oct, serde_json::to_vec(&set)?)?;
+        let oct = JwtKey::Jwks(oct.to_str().unwrap().into());
+        let secret = JwtKey::Secret("jwttokensecret".parse()?);
+        let token =
+            process_jwt_sign(claims(exp), JwtAlg::Hs256, &secret, Some("hs".into())).await?;
+        let report = process_jwt_verify(&token, &JwtValidation::default(), &oct).await?;
+        assert!(report.valid, "{:?}", report.error);
+        let report = serde_json::to_value(&report)?;
+        assert!(report.get("key_fingerprint").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_jwt_secret_sources() -> Result<()> {
+        let secret: JwtSecret = "s3cr3t".parse()?;
+        assert_eq!(format!("{:?}", secret), "JwtSecret(<redacted>)");
+        assert!("".parse::<JwtSecret>().is_err());
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("jwt_secret.txt");
+        fs::write(&path, "s3cr3t\r\n")?;
+        let from_file = JwtSecret::from_file(path.to_str().unwrap())?;
+        assert_eq!(from_file.as_bytes(), secret.as_bytes());
+        assert!(JwtSecret::from_env("RCLI_TEST_JWT_SECRET_UNSET").is_err());
+        Ok(())
+    }
 }
@@ -1,22 +1,40 @@
-use crate::genpass_process;
 use crate::utils::get_writer;
-use crate::{utils::get_reader, SignFormat};
+use crate::{age_decrypt, is_age, AGE_PEEK_LEN};
+use crate::{
+    decode_armor, encode_armor, is_armored, key_id, Cipher, Envelope, EnvelopeMode, KdfParams,
+    MAGIC,
+};
+use crate::{
+    decode_key, ed25519_fingerprint, encode_key, parse_signing_key, parse_verifying_key,
+    read_key_file, wrap_key,
+};
+use crate::{
+    hash_file, read_all, read_passphrase, utils::get_reader, HashAlgorithm, KeyEncoding, SignFormat,
+};
+use aes_gcm::Aes256Gcm;
+use aes_gcm_siv::Aes256GcmSiv;
 use anyhow::{Ok, Result};
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::Engine;
-use chacha20poly1305::{
-    aead::{Aead, KeyInit},
-    ChaCha20Poly1305,
+use chacha20poly1305::aead::{
+    consts::U32, generic_array::typenum::Unsigned, Aead, AeadCore, AeadInPlace, KeyInit,
+    KeySizeUser, Nonce, Payload,
 };
-use chacha20poly1305::{AeadCore, Key};
-use ed25519_dalek::SecretKey;
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
 use ed25519_dalek::Signature;
 use ed25519_dalek::Signer;
 use ed25519_dalek::SigningKey;
+use ed25519_dalek::VerifyingKey;
+use hmac::{Hmac, Mac};
 use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
 use std::io::Write;
+use std::marker::PhantomData;
 use std::path::Path;
 use std::{fs, io::Read};
+use zeroize::Zeroizing;
 
 trait TextSigned {
     fn sign(&self, reader: &mut dyn Read) -> Result<Vec<u8>>;
@@ -24,6 +42,10 @@ trait TextSigned {
 
 trait TextVerify {
     fn verify(&self, reader: &mut dyn Read, sign: &[u8]) -> Result<bool>;
+    /// See [`VerifyReport::key_fingerprint`].
+    fn fingerprint(&self) -> String {
+        String::new()
+    }
 }
 
 trait GenKeyOutput {
@@ -32,21 +54,58 @@ trait GenKeyOutput {
 
 struct GenKey {
     path: String,
+    format: SignFormat,
+    encoding: KeyEncoding,
+    passphrase: Option<Zeroizing<String>>,
 }
 
 impl GenKey {
-    fn new(path: &str) -> Self {
+    fn new(
+        path: &str,
+        format: SignFormat,
+        encoding: KeyEncoding,
+        passphrase: Option<&str>,
+    ) -> Self {
         GenKey {
             path: path.to_string(),
+            format,
+            encoding,
+            passphrase: passphrase.map(|p| Zeroizing::new(p.to_string())),
+        }
+    }
+
+    /// Encode a secret key, wrapping it when a passphrase was given.
+    fn encode_secret(&self, key: &[u8; 32]) -> Result<Vec<u8>> {
+        let encoded = encode_key(key, self.encoding);
+        match &self.passphrase {
+            Some(passphrase) => Ok(wrap_key(&encoded, passphrase)?.into_bytes()),
+            None => Ok(encoded),
         }
     }
 }
 
 impl GenKeyOutput for GenKey {
     async fn gen_key(&self) -> Result<()> {
-        let mut writer = get_writer(&self.path)?;
-        let key = genpass_process(32, false, false, false, false).await?;
-        writer.write_all(key.as_bytes())?;
+        match self.format {
+            SignFormat::Blake3 | SignFormat::HmacSha256 | SignFormat::HmacSha512 => {
+                let mut key = [0u8; 32];
+                OsRng.fill_bytes(&mut key);
+                get_writer(&self.path)?.write_all(&self.encode_secret(&key)?)?;
+            }
+            SignFormat::Ed25519 | SignFormat::Ed25519ph => {
+                if self.path == "-" {
+                    return Err(anyhow::anyhow!(
+                        "An output path is required for an ed25519 keypair"
+                    ));
+                }
+                // the signing key goes to `path`, the public key next to it in `path.pub`
+                let key = SigningKey::generate(&mut OsRng);
+                let public = key.verifying_key().to_bytes();
+                get_writer(&self.path)?.write_all(&self.encode_secret(&key.to_bytes())?)?;
+                get_writer(&format!("{}.pub", self.path))?
+                    .write_all(&encode_key(&public, self.encoding))?;
+            }
+        }
         Ok(())
     }
 }
@@ -55,24 +114,46 @@ struct Blake3 {
     key: [u8; 32],
 }
 
-struct Ed25519 {
-    key: SecretKey,
+/// An HMAC key. Webhook secrets are shared as text, so the key file content is
+/// the key as is (without a trailing newline), whatever its length.
+struct HmacKey<M> {
+    key: Vec<u8>,
+    mac: PhantomData<M>,
 }
+
+/// An ed25519 signing key. Pure ed25519 needs the whole message in memory;
+/// with `prehashed` (Ed25519ph, RFC 8032) the input is streamed into SHA-512.
+struct Ed25519Signer {
+    key: SigningKey,
+    prehashed: bool,
+}
+
+struct Ed25519Verifier {
+    key: VerifyingKey,
+    prehashed: bool,
+}
+
+/// The SHA-512 prehash of an Ed25519ph message.
+fn prehash(reader: &mut dyn Read) -> Result<Sha512> {
+    let mut hasher = Sha512::new();
+    read_all(reader, |buf| hasher.update(buf))?;
+    Ok(hasher)
+}
+
 impl TextSigned for Blake3 {
     fn sign(&self, reader: &mut dyn Read) -> Result<Vec<u8>> {
-        let mut buf = Vec::new();
-        reader.read_to_end(&mut buf)?;
-        Ok(blake3::keyed_hash(&self.key, &buf).as_bytes().into())
+        Ok(self.keyed_hash(reader)?.as_bytes().into())
     }
 }
 
 impl TextVerify for Blake3 {
     fn verify(&self, reader: &mut dyn Read, sign: &[u8]) -> Result<bool> {
-        let mut buf = Vec::new();
-        reader.read_to_end(&mut buf)?;
-        let hash = blake3::keyed_hash(&self.key, &buf);
-        let signed = hash.as_bytes();
-        Ok(sign == signed)
+        let hash = self.keyed_hash(reader)?;
+        let Result::Ok(sign) = <[u8; 32]>::try_from(sign) else {
+            return Ok(false);
+        };
+        // `blake3::Hash` compares in constant time
+        Ok(hash == sign)
     }
 }
 
@@ -81,61 +162,214 @@ impl Blake3 {
         Blake3 { key }
     }
 
+    fn keyed_hash(&self, reader: &mut dyn Read) -> Result<blake3::Hash> {
+        let mut hasher = blake3::Hasher::new_keyed(&self.key);
+        hasher.update_reader(reader)?;
+        Ok(hasher.finalize())
+    }
+
     fn try_new(key: &[u8]) -> Result<Self> {
-        let key: [u8; 32] = key[..32].try_into()?;
+        let key = decode_key(key)?;
         Ok(Self::new(key))
     }
 
-    fn load(path: impl AsRef<Path>) -> Result<Self> {
-        let res = fs::read(path)?;
+    fn load(path: impl AsRef<Path>, passphrase_file: Option<&str>) -> Result<Self> {
+        let res = read_key_file(path, passphrase_file)?;
         Self::try_new(&res)
     }
 }
 
-impl TextSigned for Ed25519 {
+impl<M: Mac + KeyInit> HmacKey<M> {
+    fn try_new(key: &[u8]) -> Result<Self> {
+        let key = key.strip_suffix(b"\n").unwrap_or(key);
+        let key = key.strip_suffix(b"\r").unwrap_or(key);
+        if key.is_empty() {
+            return Err(anyhow::anyhow!("HMAC key must not be empty"));
+        }
+        if key.trim_ascii_start().starts_with(b"-----BEGIN") {
+            return Err(anyhow::anyhow!(
+                "Invalid HMAC key: expected a shared secret, found a PEM key"
+            ));
+        }
+        Ok(HmacKey {
+            key: key.to_vec(),
+            mac: PhantomData,
+        })
+    }
+
+    fn load(path: impl AsRef<Path>, passphrase_file: Option<&str>) -> Result<Self> {
+        let res = read_key_file(path, passphrase_file)?;
+        Self::try_new(&res)
+    }
+
+    fn mac(&self, reader: &mut dyn Read) -> Result<M> {
+        let mut mac = <M as KeyInit>::new_from_slice(&self.key)?;
+        read_all(reader, |buf| mac.update(buf))?;
+        Ok(mac)
+    }
+}
+
+impl<M: Mac + KeyInit> TextSigned for HmacKey<M> {
     fn sign(&self, reader: &mut dyn Read) -> Result<Vec<u8>> {
-        let mut buf = Vec::new();
-        reader.read_to_end(&mut buf)?;
-        let signing_key = SigningKey::from(self.key);
-        let signed = signing_key.sign(&buf);
+        Ok(self.mac(reader)?.finalize().into_bytes().to_vec())
+    }
+}
+
+impl<M: Mac + KeyInit> TextVerify for HmacKey<M> {
+    fn verify(&self, reader: &mut dyn Read, sign: &[u8]) -> Result<bool> {
+        // `verify_slice` compares in constant time
+        Ok(self.mac(reader)?.verify_slice(sign).is_ok())
+    }
+}
+
+impl TextSigned for Ed25519Signer {
+    fn sign(&self, reader: &mut dyn Read) -> Result<Vec<u8>> {
+        let signed = if self.prehashed {
+            self.key.sign_prehashed(prehash(reader)?, None)?
+        } else {
+            let mut buf = Vec::new();
+            reader.read_to_end(&mut buf)?;
+            self.key.sign(&buf)
+        };
         Ok(signed.to_bytes().into())
     }
 }
 
-impl TextVerify for Ed25519 {
+impl TextVerify for Ed25519Verifier {
     fn verify(&self, reader: &mut dyn Read, sign: &[u8]) -> Result<bool> {
+        let Result::Ok(sign) = sign.try_into() else {
+            return Ok(false);
+        };
+        let signature = Signature::from_bytes(sign);
+        if self.prehashed {
+            let prehashed = prehash(reader)?;
+            return Ok(self
+                .key
+                .verify_prehashed_strict(prehashed, None, &signature)
+                .is_ok());
+        }
         let mut buf = Vec::new();
         reader.read_to_end(&mut buf)?;
-        let verifyer = SigningKey::from(self.key).verifying_key();
-        let signature = Signature::from_bytes(sign.try_into()?);
-        Ok(verifyer.verify_strict(&buf, &signature).is_ok())
+        Ok(self.key.verify_strict(&buf, &signature).is_ok())
+    }
+
+    fn fingerprint(&self) -> String {
+        ed25519_fingerprint(&self.key)
     }
 }
 
-impl Ed25519 {
-    fn new(key: [u8; 32]) -> Self {
-        Ed25519 { key }
+impl Ed25519Signer {
+    fn new(key: SigningKey) -> Self {
+        Ed25519Signer {
+            key,
+            prehashed: false,
+        }
+    }
+
+    fn prehashed(mut self) -> Self {
+        self.prehashed = true;
+        self
     }
 
     fn try_new(key: &[u8]) -> Result<Self> {
-        let key: [u8; 32] = key[..32].try_into()?;
-        Ok(Self::new(key))
+        Ok(Self::new(parse_signing_key(key)?))
     }
 
-    fn load(path: impl AsRef<Path>) -> Result<Self> {
-        let res = fs::read(path)?;
+    fn load(path: impl AsRef<Path>, passphrase_file: Option<&str>) -> Result<Self> {
+        let res = read_key_file(path, passphrase_file)?;
         Self::try_new(&res)
     }
 }
 
-pub async fn process_sign(input: &str, key: &str, format: SignFormat) -> Result<Vec<u8>> {
-    let mut reader = get_reader(input)?;
-    // let key = fs::read(key)?;
-    let signed: Box<dyn TextSigned> = match format {
-        SignFormat::Blake3 => Box::new(Blake3::load(key)?),
-        SignFormat::Ed25519 => Box::new(Ed25519::load(key)?),
+impl Ed25519Verifier {
+    fn new(key: VerifyingKey) -> Self {
+        Ed25519Verifier {
+            key,
+            prehashed: false,
+        }
+    }
+
+    fn prehashed(mut self) -> Self {
+        self.prehashed = true;
+        self
+    }
+
+    fn try_new(key: &[u8]) -> Result<Self> {
+        Ok(Self::new(parse_verifying_key(key)?))
+    }
+
+    fn load(path: impl AsRef<Path>, passphrase_file: Option<&str>) -> Result<Self> {
+        let res = read_key_file(path, passphrase_file)?;
+        Self::try_new(&res)
+    }
+}
+
+fn load_signer(
+    key: &str,
+    format: SignFormat,
+    passphrase_file: Option<&str>,
+) -> Result<Box<dyn TextSigned>> {
+    let signer: Box<dyn TextSigned> = match format {
+        SignFormat::Blake3 => Box::new(Blake3::load(key, passphrase_file)?),
+        SignFormat::Ed25519 => Box::new(Ed25519Signer::load(key, passphrase_file)?),
+        SignFormat::Ed25519ph => Box::new(Ed25519Signer::load(key, passphrase_file)?.prehashed()),
+        SignFormat::HmacSha256 => Box::new(HmacKey::<Hmac<Sha256>>::load(key, passphrase_file)?),
+        SignFormat::HmacSha512 => Box::new(HmacKey::<Hmac<Sha512>>::load(key, passphrase_file)?),
+    };
+    Ok(signer)
+}
+
+fn load_verifier(
+    key: &str,
+    format: SignFormat,
+    passphrase_file: Option<&str>,
+) -> Result<Box<dyn TextVerify>> {
+    let verifier: Box<dyn TextVerify> = match format {
+        SignFormat::Blake3 => Box::new(Blake3::load(key, passphrase_file)?),
+        SignFormat::Ed25519 => Box::new(Ed25519Verifier::load(key, passphrase_file)?),
+        SignFormat::Ed25519ph => Box::new(Ed25519Verifier::load(key, passphrase_file)?.prehashed()),
+        SignFormat::HmacSha256 => Box::new(HmacKey::<Hmac<Sha256>>::load(key, passphrase_file)?),
+        SignFormat::HmacSha512 => Box::new(HmacKey::<Hmac<Sha512>>::load(key, passphrase_file)?),
     };
-    signed.sign(&mut reader)
+    Ok(verifier)
+}
+
+pub async fn process_sign(
+    input: &str,
+    key: &str,
+    format: SignFormat,
+    passphrase_file: Option<&str>,
+) -> Result<Vec<u8>> {
+    let mut reader = get_reader(input)?;
+    load_signer(key, format, passphrase_file)?.sign(&mut reader)
+}
+
+/// Outcome of a signature check, as printed by `text verify --json`.
+#[derive(Debug, Serialize)]
+pub struct VerifyReport {
+    pub valid: bool,
+    pub algorithm: String,
+    /// Identifies the public key that checked the signature. Secret keys get
+    /// none, and neither do the secrets behind the JWT and PASETO reports:
+    /// even a hash of a low-entropy secret can be brute-forced offline.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub key_fingerprint: String,
+}
+
+pub async fn process_verify_report(
+    input: &str,
+    key: &str,
+    format: SignFormat,
+    sign: &[u8],
+    passphrase_file: Option<&str>,
+) -> Result<VerifyReport> {
+    let verifier = load_verifier(key, format, passphrase_file)?;
+    let mut reader = get_reader(input)?;
+    Ok(VerifyReport {
+        valid: verifier.verify(&mut reader, sign)?,
+        algorithm: format.to_string(),
+        key_fingerprint: verifier.fingerprint(),
+    })
 }
 
 pub async fn process_verify(
@@ -143,37 +377,281 @@ pub async fn process_verify(
     key: &str,
     format: SignFormat,
     sign: &[u8],
+    passphrase_file: Option<&str>,
 ) -> Result<bool> {
-    let mut reader = get_reader(input)?;
-    let verifier: Box<dyn TextVerify> = match format {
-        SignFormat::Blake3 => Box::new(Blake3::load(key)?),
-        SignFormat::Ed25519 => Box::new(Ed25519::load(key)?),
+    Ok(
+        process_verify_report(input, key, format, sign, passphrase_file)
+            .await?
+            .valid,
+    )
+}
+
+/// A signed listing of every file under a directory, used to verify a set of
+/// release artifacts in one go.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: String,
+    pub files: Vec<ManifestEntry>,
+    /// Signature of [`Manifest::digest`], covering the format and every entry.
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub blake3: String,
+}
+
+/// Outcome of `text verify-manifest`.
+#[derive(Debug)]
+pub struct ManifestCheck {
+    /// Whether the manifest signature is valid. When it is not, no entry can
+    /// be trusted and every file is reported as failed.
+    pub signature_valid: bool,
+    /// The result for each path, in manifest order.
+    pub files: Vec<(String, bool)>,
+    /// Files under the directory that the manifest does not list.
+    pub unlisted: Vec<String>,
+}
+
+impl Manifest {
+    const CONTEXT: &'static str = "rcli manifest v1";
+
+    /// BLAKE3 of the canonical JSON encoding of the format and entries, so that
+    /// entries cannot be moved, dropped or added without breaking the signature.
+    fn digest(format: &str, files: &[ManifestEntry]) -> Result<blake3::Hash> {
+        let canonical = serde_json::to_vec(&(Self::CONTEXT, format, files))?;
+        Ok(blake3::hash(&canonical))
+    }
+}
+
+pub async fn process_sign_manifest(
+    dir: &str,
+    key: &str,
+    format: SignFormat,
+    passphrase_file: Option<&str>,
+) -> Result<Manifest> {
+    let signer = load_signer(key, format, passphrase_file)?;
+    let root = Path::new(dir);
+    let mut files = Vec::new();
+    for path in list_files(root)? {
+        let hash = hash_file(HashAlgorithm::Blake3, &root.join(&path))?;
+        files.push(ManifestEntry { path, blake3: hash });
+    }
+    let format = format.to_string();
+    let digest = Manifest::digest(&format, &files)?;
+    let signature = signer.sign(&mut digest.as_bytes().as_slice())?;
+    Ok(Manifest {
+        format,
+        files,
+        signature: URL_SAFE_NO_PAD.encode(signature),
+    })
+}
+
+/// Verify a manifest and every file it lists against the files under `dir`.
+/// The manifest must have been signed with `format`: the format recorded in
+/// the manifest is not trusted to pick the algorithm.
+pub async fn process_verify_manifest(
+    dir: &str,
+    manifest_path: &str,
+    key: &str,
+    format: SignFormat,
+    passphrase_file: Option<&str>,
+) -> Result<ManifestCheck> {
+    let manifest: Manifest = serde_json::from_reader(get_reader(manifest_path)?)?;
+    if manifest.format != format.to_string() {
+        return Err(anyhow::anyhow!(
+            "Manifest is signed with {}, expected {}",
+            manifest.format,
+            format
+        ));
+    }
+    let verifier = load_verifier(key, format, passphrase_file)?;
+    let digest = Manifest::digest(&manifest.format, &manifest.files)?;
+    let signature_valid = match URL_SAFE_NO_PAD.decode(&manifest.signature) {
+        Result::Ok(sign) => verifier.verify(&mut digest.as_bytes().as_slice(), &sign)?,
+        Err(_) => false,
     };
-    verifier.verify(&mut reader, sign)
+
+    let root = Path::new(dir);
+    let mut files = Vec::with_capacity(manifest.files.len());
+    for entry in &manifest.files {
+        let path = root.join(&entry.path);
+        let valid = signature_valid
+            && path.is_file()
+            && hash_file(HashAlgorithm::Blake3, &path)? == entry.blake3;
+        files.push((entry.path.clone(), valid));
+    }
+
+    // the manifest itself may be stored in the directory it covers
+    let manifest_file = fs::canonicalize(manifest_path).ok();
+    let mut unlisted = Vec::new();
+    for path in list_files(root)? {
+        if manifest.files.iter().any(|entry| entry.path == path)
+            || (manifest_file.is_some() && fs::canonicalize(root.join(&path)).ok() == manifest_file)
+        {
+            continue;
+        }
+        unlisted.push(path);
+    }
+    Ok(ManifestCheck {
+        signature_valid,
+        files,
+        unlisted,
+    })
 }
 
-pub async fn process_gen_key(path: &str) -> Result<()> {
-    let opt = GenKey::new(path);
+/// Collect all regular files below `root` as sorted, `/`-separated paths
+/// relative to `root`.
+pub(crate) fn list_files(root: &Path) -> Result<Vec<String>> {
+    fn walk(root: &Path, dir: &Path, res: &mut Vec<String>) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                walk(root, &path, res)?;
+            } else if path.is_file() {
+                let rel = path.strip_prefix(root)?;
+                let rel: Vec<_> = rel.iter().map(|p| p.to_string_lossy()).collect();
+                res.push(rel.join("/"));
+            }
+        }
+        Ok(())
+    }
+    let mut res = Vec::new();
+    walk(root, root, &mut res)?;
+    res.sort();
+    Ok(res)
+}
+
+pub async fn process_gen_key(
+    path: &str,
+    format: SignFormat,
+    encoding: KeyEncoding,
+    passphrase: Option<&str>,
+) -> Result<()> {
+    let opt = GenKey::new(path, format, encoding, passphrase);
     opt.gen_key().await
 }
 
-pub struct ChaCha20Poly {
-    pub key: Key,
+/// An AEAD cipher that can be named in the envelope header.
+pub(crate) trait EnvelopeCipher: AeadInPlace + KeyInit + KeySizeUser<KeySize = U32> {
+    const CIPHER: Cipher;
 }
 
-impl ChaCha20Poly {
-    fn new(key: [u8; 32]) -> Self {
-        ChaCha20Poly { key: key.into() }
+impl EnvelopeCipher for ChaCha20Poly1305 {
+    const CIPHER: Cipher = Cipher::ChaCha20Poly1305;
+}
+
+impl EnvelopeCipher for XChaCha20Poly1305 {
+    const CIPHER: Cipher = Cipher::XChaCha20Poly1305;
+}
+
+impl EnvelopeCipher for Aes256Gcm {
+    const CIPHER: Cipher = Cipher::Aes256Gcm;
+}
+
+impl EnvelopeCipher for Aes256GcmSiv {
+    const CIPHER: Cipher = Cipher::Aes256GcmSiv;
+}
+
+/// A 256-bit key for one of the AEAD ciphers.
+pub(crate) struct AeadKey<A> {
+    pub key: [u8; 32],
+    kdf: Option<KdfParams>,
+    aad: Option<Vec<u8>>,
+    cipher: PhantomData<A>,
+}
+
+pub(crate) type ChaCha20Poly = AeadKey<ChaCha20Poly1305>;
+
+fn as_nonce<A: AeadCore>(nonce: &[u8]) -> Result<&Nonce<A>> {
+    if nonce.len() != A::NonceSize::USIZE {
+        return Err(anyhow::anyhow!("Invalid nonce length {}", nonce.len()));
     }
+    Ok(Nonce::<A>::from_slice(nonce))
+}
 
-    fn try_new(key: &[u8]) -> Result<Self> {
-        let k: [u8; 32] = key[..32].try_into()?;
-        Ok(Self::new(k))
+impl<A: EnvelopeCipher> AeadKey<A> {
+    pub(crate) fn new(key: [u8; 32]) -> Self {
+        AeadKey {
+            key,
+            kdf: None,
+            aad: None,
+            cipher: PhantomData,
+        }
     }
 
-    fn load(key_path: impl AsRef<Path>) -> Result<Self> {
-        let key = fs::read(key_path)?;
-        Self::try_new(&key)
+    fn envelope(&self, mode: EnvelopeMode, nonce: &[u8]) -> Envelope {
+        let mut envelope = Envelope::new(A::CIPHER, mode, self.key_id(), nonce);
+        if let Some(kdf) = &self.kdf {
+            envelope = envelope.with_kdf(kdf.clone());
+        }
+        if self.aad.is_some() {
+            envelope = envelope.with_aad();
+        }
+        envelope
+    }
+
+    /// The associated data of every AEAD operation: the encoded header,
+    /// followed by the external associated data if any.
+    fn associated_data(&self, envelope: &Envelope) -> Result<Vec<u8>> {
+        match (envelope.has_aad(), &self.aad) {
+            (true, None) => Err(anyhow::anyhow!(
+                "Message is bound to associated data, it must be given to decrypt"
+            )),
+            (false, Some(_)) => Err(anyhow::anyhow!("Message has no associated data")),
+            (_, aad) => {
+                let mut data = envelope.to_bytes();
+                data.extend(aad.iter().flatten());
+                Ok(data)
+            }
+        }
+    }
+
+    fn aead(&self) -> A {
+        A::new(&self.key.into())
+    }
+
+    fn key_id(&self) -> [u8; 8] {
+        key_id(&self.key)
+    }
+
+    /// Encrypt `plaintext` under a fresh random nonce, returning the nonce and
+    /// the ciphertext with its tag.
+    pub(crate) fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+        let nonce = A::generate_nonce(&mut OsRng);
+        let ciphertext = self.seal_with_nonce(&nonce, plaintext, aad)?;
+        Ok((nonce.to_vec(), ciphertext))
+    }
+
+    fn seal_with_nonce(&self, nonce: &[u8], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        Ok(self.aead().encrypt(as_nonce::<A>(nonce)?, payload)?)
+    }
+
+    pub(crate) fn open(&self, nonce: &[u8], ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        Ok(self.aead().decrypt(as_nonce::<A>(nonce)?, payload)?)
+    }
+}
+
+impl ChaCha20Poly {
+    /// Read the `ciphertext|nonce` format written before the envelope existed.
+    fn decrypt_legacy(&self, text: &str) -> Result<Vec<u8>> {
+        let (encrypted, nonce) = text
+            .trim()
+            .split_once('|')
+            .ok_or_else(|| anyhow::anyhow!("Not an encrypted message"))?;
+        let encrypted = URL_SAFE_NO_PAD.decode(encrypted)?;
+        let nonce = URL_SAFE_NO_PAD.decode(nonce)?;
+        self.open(&nonce, &encrypted, b"")
+            .map_err(|_| anyhow::anyhow!("Decryption failed: wrong key or corrupted message"))
     }
 }
 
@@ -182,48 +660,650 @@ trait Encrypt {
 }
 
 trait Decrypt {
-    fn decrypt(&self, reader: &mut dyn Read) -> Result<String>;
+    fn decrypt(&self, reader: &mut dyn Read) -> Result<Vec<u8>>;
+}
+
+trait StreamEncrypt {
+    fn encrypt_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<()>;
 }
 
-impl Encrypt for ChaCha20Poly {
+trait StreamDecrypt {
+    fn decrypt_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<()>;
+}
+
+trait TextCipher: Encrypt + Decrypt + StreamEncrypt + StreamDecrypt {}
+
+impl<T: Encrypt + Decrypt + StreamEncrypt + StreamDecrypt> TextCipher for T {}
+
+impl<A: EnvelopeCipher> Encrypt for AeadKey<A> {
+    /// Encrypt the input, byte for byte, and put out an armored envelope.
     fn encrypt(&self, reader: &mut dyn Read) -> Result<String> {
-        let mut buf = String::new();
-        reader.read_to_string(&mut buf)?;
-        let buf = buf.trim();
-        let cipher = ChaCha20Poly1305::new(&self.key);
-        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng); // 192-bits; unique per message
-        let ciphertext = cipher.encrypt(&nonce, buf.as_ref())?;
-        Ok(format!(
-            "{}|{}",
-            URL_SAFE_NO_PAD.encode(ciphertext),
-            URL_SAFE_NO_PAD.encode(nonce)
-        ))
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        let nonce = A::generate_nonce(&mut OsRng);
+        let envelope = self.envelope(EnvelopeMode::Single, &nonce);
+        let aad = self.associated_data(&envelope)?;
+        let mut message = envelope.to_bytes();
+        let ciphertext = self.seal_with_nonce(&nonce, &buf, &aad)?;
+        message.extend(ciphertext);
+        encode_armor(&message)
     }
 }
 
-impl Decrypt for ChaCha20Poly {
-    fn decrypt(&self, reader: &mut dyn Read) -> Result<String> {
+impl<A: EnvelopeCipher> Decrypt for AeadKey<A> {
+    /// Decrypt an armored envelope.
+    fn decrypt(&self, reader: &mut dyn Read) -> Result<Vec<u8>> {
         let mut buf = String::new();
         reader.read_to_string(&mut buf)?;
-        let index = buf.find('|').unwrap();
+        let message = decode_armor(&buf)?;
+        let mut plaintext = Vec::new();
+        self.decrypt_stream(&mut message.as_slice(), &mut plaintext)?;
+        Ok(plaintext)
+    }
+}
+
+/// Plaintext bytes per segment of a chunked stream.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+/// Authentication tag appended to every segment.
+const STREAM_TAG_SIZE: usize = 16;
+
+/// Read up to `size` bytes, stopping early only at the end of input.
+fn read_chunk(reader: &mut dyn Read, size: usize) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(size);
+    reader.take(size as u64).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Nonce of a STREAM segment (as in `aead::stream::StreamBE32`): the random
+/// prefix, a 32-bit big-endian segment counter and a last-segment flag byte.
+fn stream_nonce<A: AeadCore>(prefix: &[u8], counter: u32, last: bool) -> Nonce<A> {
+    let mut nonce = Nonce::<A>::default();
+    let split = nonce.len() - 5;
+    nonce[..split].copy_from_slice(prefix);
+    nonce[split..split + 4].copy_from_slice(&counter.to_be_bytes());
+    nonce[split + 4] = last as u8;
+    nonce
+}
 
-        let encrypted = URL_SAFE_NO_PAD.decode(&buf[..index])?;
-        let nonce = URL_SAFE_NO_PAD.decode(&buf[index + 1..])?;
-        let nonce: [u8; 12] = nonce[..12].try_into()?;
-        let cipher = ChaCha20Poly1305::new(&self.key);
-        let plaintext = cipher.decrypt(&nonce.into(), encrypted.as_ref())?;
-        Ok(String::from_utf8(plaintext)?)
+impl<A: EnvelopeCipher> StreamEncrypt for AeadKey<A> {
+    /// Encrypt a binary stream of any size in constant memory. The output is
+    /// a binary envelope followed by 64 KiB segments, each with its own tag;
+    /// the final segment is flagged so that truncation is detected.
+    fn encrypt_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<()> {
+        let mut prefix = vec![0u8; A::NonceSize::USIZE - 5];
+        OsRng.fill_bytes(&mut prefix);
+        let envelope = self.envelope(EnvelopeMode::Stream, &prefix);
+        let aad = self.associated_data(&envelope)?;
+        let aead = self.aead();
+        writer.write_all(&envelope.to_bytes())?;
+
+        let mut chunk = read_chunk(reader, STREAM_CHUNK_SIZE)?;
+        let mut counter = 0u32;
+        loop {
+            let next = if chunk.len() == STREAM_CHUNK_SIZE {
+                read_chunk(reader, STREAM_CHUNK_SIZE)?
+            } else {
+                Vec::new()
+            };
+            let last = next.is_empty();
+            let payload = Payload {
+                msg: &chunk,
+                aad: &aad,
+            };
+            let nonce = stream_nonce::<A>(&prefix, counter, last);
+            writer.write_all(&aead.encrypt(&nonce, payload)?)?;
+            if last {
+                break;
+            }
+            counter = counter
+                .checked_add(1)
+                .ok_or_else(|| anyhow::anyhow!("Input is too large for one stream"))?;
+            chunk = next;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<A: EnvelopeCipher> StreamDecrypt for AeadKey<A> {
+    /// Decrypt a binary envelope in either mode, writing the plaintext out as
+    /// it is authenticated.
+    fn decrypt_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<()> {
+        let envelope = Envelope::read_from(reader)?;
+        if envelope.cipher != A::CIPHER {
+            return Err(anyhow::anyhow!(
+                "Message was encrypted with {}, not {}",
+                envelope.cipher,
+                A::CIPHER
+            ));
+        }
+        envelope.check_key_id(self.key_id())?;
+        let aad = self.associated_data(&envelope)?;
+        let failed = || anyhow::anyhow!("Decryption failed: wrong key or corrupted message");
+
+        if envelope.mode == EnvelopeMode::Single {
+            let mut ciphertext = Vec::new();
+            reader.read_to_end(&mut ciphertext)?;
+            let plaintext = self
+                .open(&envelope.nonce, &ciphertext, &aad)
+                .map_err(|_| failed())?;
+            writer.write_all(&plaintext)?;
+            return Ok(());
+        }
+
+        if envelope.nonce.len() != A::NonceSize::USIZE - 5 {
+            return Err(anyhow::anyhow!("Invalid stream nonce length"));
+        }
+        let aead = self.aead();
+        let size = STREAM_CHUNK_SIZE + STREAM_TAG_SIZE;
+        let mut chunk = read_chunk(reader, size)?;
+        let mut counter = 0u32;
+        loop {
+            let next = if chunk.len() == size {
+                read_chunk(reader, size)?
+            } else {
+                Vec::new()
+            };
+            let last = next.is_empty();
+            let payload = Payload {
+                msg: &chunk,
+                aad: &aad,
+            };
+            let nonce = stream_nonce::<A>(&envelope.nonce, counter, last);
+            writer.write_all(&aead.decrypt(&nonce, payload).map_err(|_| failed())?)?;
+            if last {
+                break;
+            }
+            counter = counter.checked_add(1).ok_or_else(failed)?;
+            chunk = next;
+        }
+        writer.flush()?;
+        Ok(())
     }
 }
 
-pub async fn process_text_encrypt(key: &str, input: &str) -> Result<String> {
-    let chacha = ChaCha20Poly::load(key)?;
+fn load_symmetric_key(path: &str, passphrase_file: Option<&str>) -> Result<[u8; 32]> {
+    decode_key(&read_key_file(path, passphrase_file)?)
+}
+
+/// The key to encrypt with: read from the key file, or derived from a
+/// password with fresh Argon2id parameters when no key file is given.
+fn encryption_key(
+    key: Option<&str>,
+    passphrase_file: Option<&str>,
+) -> Result<([u8; 32], Option<KdfParams>)> {
+    match key {
+        Some(key) => Ok((load_symmetric_key(key, passphrase_file)?, None)),
+        None => {
+            let password = read_passphrase(passphrase_file, true)?;
+            let kdf = KdfParams::generate();
+            Ok((kdf.derive_key(&password)?, Some(kdf)))
+        }
+    }
+}
+
+/// The key to decrypt `envelope` with, derived from the password when the
+/// message was encrypted with one.
+fn decryption_key(
+    key: Option<&str>,
+    envelope: &Envelope,
+    passphrase_file: Option<&str>,
+) -> Result<[u8; 32]> {
+    match (&envelope.kdf, key) {
+        (Some(kdf), _) => kdf.derive_key(&read_passphrase(passphrase_file, false)?),
+        (None, Some(key)) => load_symmetric_key(key, passphrase_file),
+        (None, None) => Err(anyhow::anyhow!(
+            "Message is not password protected, a key is required"
+        )),
+    }
+}
+
+fn load_cipher(
+    cipher: Cipher,
+    key: [u8; 32],
+    kdf: Option<KdfParams>,
+    aad: Option<&[u8]>,
+) -> Box<dyn TextCipher> {
+    fn boxed<A: EnvelopeCipher + 'static>(
+        key: [u8; 32],
+        kdf: Option<KdfParams>,
+        aad: Option<&[u8]>,
+    ) -> Box<dyn TextCipher> {
+        let mut key = AeadKey::<A>::new(key);
+        key.kdf = kdf;
+        key.aad = aad.map(<[u8]>::to_vec);
+        Box::new(key)
+    }
+    match cipher {
+        Cipher::ChaCha20Poly1305 => boxed::<ChaCha20Poly1305>(key, kdf, aad),
+        Cipher::XChaCha20Poly1305 => boxed::<XChaCha20Poly1305>(key, kdf, aad),
+        Cipher::Aes256Gcm => boxed::<Aes256Gcm>(key, kdf, aad),
+        Cipher::Aes256GcmSiv => boxed::<Aes256GcmSiv>(key, kdf, aad),
+    }
+}
+
+/// Encrypt a text with the key file `key`, or with a password when `key` is
+/// `None`.
+pub async fn process_text_encrypt(
+    key: Option<&str>,
+    input: &str,
+    cipher: Cipher,
+    aad: Option<&[u8]>,
+    passphrase_file: Option<&str>,
+) -> Result<String> {
+    let (key, kdf) = encryption_key(key, passphrase_file)?;
+    let cipher = load_cipher(cipher, key, kdf, aad);
+    let mut reader = get_reader(input)?;
+    cipher.encrypt(&mut reader)
+}
+
+pub async fn process_stream_encrypt(
+    key: Option<&str>,
+    input: &str,
+    output: &str,
+    cipher: Cipher,
+    aad: Option<&[u8]>,
+    passphrase_file: Option<&str>,
+) -> Result<()> {
+    let (key, kdf) = encryption_key(key, passphrase_file)?;
+    let cipher = load_cipher(cipher, key, kdf, aad);
     let mut reader = get_reader(input)?;
-    chacha.encrypt(&mut reader)
+    let mut writer = get_writer(output)?;
+    cipher.encrypt_stream(&mut reader, &mut writer)
 }
 
-pub async fn process_text_decrypt(key: &str, enctypted: &str) -> Result<String> {
-    let chacha = ChaCha20Poly::load(key)?;
-    let mut reader = get_reader(enctypted)?;
-    chacha.decrypt(&mut reader)
+/// Decrypt any supported input: a binary envelope (streamed), an armored
+/// envelope, an age file, or the legacy `ciphertext|nonce` text. The cipher,
+/// and whether the key comes from a password, are taken from the header.
+/// `aad` must match the associated data given at encryption.
+pub async fn process_text_decrypt(
+    key: Option<&str>,
+    identities: &[String],
+    input: &str,
+    output: &str,
+    aad: Option<&[u8]>,
+    passphrase_file: Option<&str>,
+) -> Result<()> {
+    let no_aad = || anyhow::anyhow!("Message has no associated data");
+    let mut reader = get_reader(input)?;
+    let mut writer = get_writer(output)?;
+    let head = read_chunk(&mut reader, AGE_PEEK_LEN)?;
+    let mut reader = head.as_slice().chain(reader);
+    if is_age(&head) {
+        if aad.is_some() {
+            return Err(no_aad());
+        }
+        return age_decrypt(reader, &mut writer, identities, passphrase_file);
+    }
+    if head.starts_with(MAGIC) {
+        let envelope = Envelope::read_from(&mut reader)?;
+        let key = decryption_key(key, &envelope, passphrase_file)?;
+        let header = envelope.to_bytes();
+        let mut reader = header.as_slice().chain(reader);
+        return load_cipher(envelope.cipher, key, envelope.kdf, aad)
+            .decrypt_stream(&mut reader, &mut writer);
+    }
+
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    if is_armored(&text) {
+        let message = decode_armor(&text)?;
+        let envelope = Envelope::read_from(&mut message.as_slice())?;
+        let key = decryption_key(key, &envelope, passphrase_file)?;
+        let res =
+            load_cipher(envelope.cipher, key, envelope.kdf, aad).decrypt(&mut text.as_bytes())?;
+        writer.write_all(&res)?;
+    } else {
+        if aad.is_some() {
+            return Err(no_aad());
+        }
+        let key = key.ok_or_else(|| anyhow::anyhow!("A key is required for this message"))?;
+        let key = load_symmetric_key(key, passphrase_file)?;
+        writer.write_all(&ChaCha20Poly::new(key).decrypt_legacy(&text)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "fixture/text_key.txt";
+
+    #[tokio::test]
+    async fn test_blake3_sign_verify() -> Result<()> {
+        let sign = process_sign("Cargo.toml", KEY, SignFormat::Blake3, None).await?;
+        // streaming gives the same MAC as hashing the whole input at once
+        let key = decode_key(&fs::read(KEY)?)?;
+        let expected = blake3::keyed_hash(&key, &fs::read("Cargo.toml")?);
+        assert_eq!(sign, expected.as_bytes());
+        assert!(process_verify("Cargo.toml", KEY, SignFormat::Blake3, &sign, None).await?);
+        assert!(!process_verify("README.md", KEY, SignFormat::Blake3, &sign, None).await?);
+        assert!(!process_verify("Cargo.toml", KEY, SignFormat::Blake3, &sign[..31], None).await?);
+        // reports never carry anything derived from a secret key
+        let report =
+            process_verify_report("Cargo.toml", KEY, SignFormat::Blake3, &sign, None).await?;
+        assert!(report.valid && report.key_fingerprint.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hmac_sign_verify() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let key = dir.path().join("hmac.key");
+        let input = dir.path().join("hmac.txt");
+        // RFC 4231 test case 2
+        fs::write(&key, "Jefe\n")?;
+        fs::write(&input, "what do ya want for nothing?")?;
+        let (key, input) = (key.to_str().unwrap(), input.to_str().unwrap());
+
+        let sign = process_sign(input, key, SignFormat::HmacSha256, None).await?;
+        assert_eq!(
+            hex::encode(&sign),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert!(process_verify(input, key, SignFormat::HmacSha256, &sign, None).await?);
+        assert!(!process_verify(input, key, SignFormat::HmacSha256, &sign[1..], None).await?);
+        assert!(!process_verify("Cargo.toml", key, SignFormat::HmacSha256, &sign, None).await?);
+
+        let sign = process_sign(input, key, SignFormat::HmacSha512, None).await?;
+        assert_eq!(
+            hex::encode(&sign),
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+             9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+        );
+        assert!(process_verify(input, key, SignFormat::HmacSha512, &sign, None).await?);
+
+        // a 64-character secret is used as text, not hex-decoded
+        let long = dir.path().join("long.key");
+        fs::write(&long, "ab".repeat(32))?;
+        let long = long.to_str().unwrap();
+        let sign = process_sign(input, long, SignFormat::HmacSha256, None).await?;
+        assert_eq!(
+            hex::encode(&sign),
+            "ba5f30d5cc393398a74e73784bc4994a53bdbda556fb20908562eab33c0c44e9"
+        );
+
+        // a public key is not an HMAC secret
+        let public = "fixture/jwt_ed25519.pub.pem";
+        assert!(process_sign(input, public, SignFormat::HmacSha256, None)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_manifest_sign_verify() -> Result<()> {
+        let manifest = process_sign_manifest("fixture/test", KEY, SignFormat::Blake3, None).await?;
+        let paths: Vec<_> = manifest.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "test-new/test-new1.txt",
+                "test-new/test-new2.txt",
+                "test1.txt",
+                "test2.txt"
+            ]
+        );
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("manifest.json");
+        let manifest_path = path.to_str().unwrap();
+        let write =
+            |manifest: &Manifest| fs::write(&path, serde_json::to_string(manifest).unwrap());
+        write(&manifest)?;
+        let res =
+            process_verify_manifest("fixture/test", manifest_path, KEY, SignFormat::Blake3, None)
+                .await?;
+        assert!(res.signature_valid);
+        assert!(res.files.iter().all(|(_, valid)| *valid));
+        assert!(res.unlisted.is_empty());
+
+        // the format is chosen by the verifier, not by the manifest
+        let res = process_verify_manifest(
+            "fixture/test",
+            manifest_path,
+            KEY,
+            SignFormat::HmacSha256,
+            None,
+        )
+        .await;
+        assert!(res.is_err());
+
+        let res = process_verify_manifest("fixture", manifest_path, KEY, SignFormat::Blake3, None)
+            .await?;
+        assert!(res.files.iter().all(|(_, valid)| !valid));
+        assert!(res.unlisted.contains(&"text_key.txt".to_string()));
+
+        // dropping an entry breaks the signature of the whole manifest
+        let mut dropped =
+            process_sign_manifest("fixture/test", KEY, SignFormat::Blake3, None).await?;
+        dropped.files.pop();
+        write(&dropped)?;
+        let res =
+            process_verify_manifest("fixture/test", manifest_path, KEY, SignFormat::Blake3, None)
+                .await?;
+        assert!(!res.signature_valid);
+        assert!(res.files.iter().all(|(_, valid)| !valid));
+        assert_eq!(res.unlisted, ["test2.txt"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ed25519_verify_with_public_key() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("ed25519.key");
+        let key = path.to_str().unwrap();
+        let public = format!("{}.pub", key);
+        process_gen_key(key, SignFormat::Ed25519, KeyEncoding::Hex, None).await?;
+
+        let sign = process_sign("Cargo.toml", key, SignFormat::Ed25519, None).await?;
+        assert!(process_verify("Cargo.toml", &public, SignFormat::Ed25519, &sign, None).await?);
+        let res = process_verify("Cargo.toml", key, SignFormat::Ed25519, &sign, None).await;
+        assert!(!matches!(res, Result::Ok(true)));
+
+        let sign = process_sign("Cargo.toml", key, SignFormat::Ed25519ph, None).await?;
+        assert!(process_verify("Cargo.toml", &public, SignFormat::Ed25519ph, &sign, None).await?);
+        assert!(!process_verify("README.md", &public, SignFormat::Ed25519ph, &sign, None).await?);
+        // an Ed25519ph signature is not a pure ed25519 signature of the same input
+        let res = process_verify("Cargo.toml", &public, SignFormat::Ed25519, &sign, None).await;
+        assert!(!matches!(res, Result::Ok(true)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_passphrase_protected_key() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let key = dir.path().join("wrapped.key");
+        let key = key.to_str().unwrap();
+        let passphrase = dir.path().join("passphrase.txt");
+        fs::write(&passphrase, "correct horse\n")?;
+        let passphrase = passphrase.to_str();
+        process_gen_key(
+            key,
+            SignFormat::Blake3,
+            KeyEncoding::Hex,
+            Some("correct horse"),
+        )
+        .await?;
+
+        let sign = process_sign("Cargo.toml", key, SignFormat::Blake3, passphrase).await?;
+        assert!(process_verify("Cargo.toml", key, SignFormat::Blake3, &sign, passphrase).await?);
+
+        let encrypted = process_text_encrypt(
+            Some(key),
+            "Cargo.toml",
+            Cipher::ChaCha20Poly1305,
+            None,
+            passphrase,
+        )
+        .await?;
+        let input = dir.path().join("wrapped.enc");
+        let output = dir.path().join("wrapped.dec");
+        fs::write(&input, encrypted)?;
+        let (input, output) = (input.to_str().unwrap(), output.to_str().unwrap());
+        process_text_decrypt(Some(key), &[], input, output, None, passphrase).await?;
+        assert_eq!(fs::read(output)?, fs::read("Cargo.toml")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_encrypt_decrypt() -> Result<()> {
+        let chacha = ChaCha20Poly::new([7u8; 32]);
+        for len in [0, 1, STREAM_CHUNK_SIZE, 2 * STREAM_CHUNK_SIZE + 3] {
+            let mut plaintext = vec![0u8; len];
+            OsRng.fill_bytes(&mut plaintext);
+            let mut encrypted = Vec::new();
+            chacha.encrypt_stream(&mut plaintext.as_slice(), &mut encrypted)?;
+
+            let mut decrypted = Vec::new();
+            chacha.decrypt_stream(&mut encrypted.as_slice(), &mut decrypted)?;
+            assert_eq!(decrypted, plaintext);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_detects_truncation() -> Result<()> {
+        let chacha = ChaCha20Poly::new([7u8; 32]);
+        let plaintext = vec![1u8; 2 * STREAM_CHUNK_SIZE + 3];
+        let mut encrypted = Vec::new();
+        chacha.encrypt_stream(&mut plaintext.as_slice(), &mut encrypted)?;
+
+        let segment = STREAM_CHUNK_SIZE + STREAM_TAG_SIZE;
+        let header = encrypted.len() - 2 * segment - 3 - STREAM_TAG_SIZE;
+        let truncated = &encrypted[..header + 2 * segment];
+        let res = chacha.decrypt_stream(&mut &truncated[..], &mut Vec::new());
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decrypt_envelope_and_legacy() -> Result<()> {
+        let chacha = ChaCha20Poly::new([7u8; 32]);
+        let encrypted = chacha.encrypt(&mut "hello world".as_bytes())?;
+        assert!(encrypted.starts_with("-----BEGIN RCLI ENCRYPTED MESSAGE-----"));
+        assert_eq!(chacha.decrypt(&mut encrypted.as_bytes())?, b"hello world");
+        // the plaintext is kept byte for byte, whitespace and non-UTF-8 included
+        let raw = b"  \xff\xfe binary\r\n\n";
+        let encrypted_raw = chacha.encrypt(&mut raw.as_slice())?;
+        assert_eq!(chacha.decrypt(&mut encrypted_raw.as_bytes())?, raw);
+
+        let (nonce, ciphertext) = chacha.seal(b"hello world", b"")?;
+        let legacy = format!(
+            "{}|{}",
+            URL_SAFE_NO_PAD.encode(ciphertext),
+            URL_SAFE_NO_PAD.encode(nonce)
+        );
+        assert_eq!(chacha.decrypt_legacy(&legacy)?, b"hello world");
+
+        assert!(chacha.decrypt_legacy("no separator").is_err());
+        let other = ChaCha20Poly::new([8u8; 32]);
+        assert!(other.decrypt(&mut encrypted.as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_password_encryption() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let password = dir.path().join("password.txt");
+        let wrong = dir.path().join("wrong_password.txt");
+        let encrypted = dir.path().join("password.enc");
+        let decrypted = dir.path().join("password.dec");
+        fs::write(&password, "open sesame\n")?;
+        fs::write(&wrong, "open says me\n")?;
+        let (password, wrong) = (password.to_str(), wrong.to_str());
+        let (encrypted, decrypted) = (encrypted.to_str().unwrap(), decrypted.to_str().unwrap());
+
+        let armored =
+            process_text_encrypt(None, "Cargo.toml", Cipher::Aes256Gcm, None, password).await?;
+        fs::write(encrypted, armored)?;
+        process_text_decrypt(None, &[], encrypted, decrypted, None, password).await?;
+        assert_eq!(fs::read(decrypted)?, fs::read("Cargo.toml")?);
+        // the key file is ignored for password protected messages
+        process_text_decrypt(Some(KEY), &[], encrypted, decrypted, None, password).await?;
+        assert!(
+            process_text_decrypt(None, &[], encrypted, decrypted, None, wrong)
+                .await
+                .is_err()
+        );
+
+        process_stream_encrypt(
+            None,
+            "Cargo.toml",
+            encrypted,
+            Cipher::ChaCha20Poly1305,
+            None,
+            password,
+        )
+        .await?;
+        process_text_decrypt(None, &[], encrypted, decrypted, None, password).await?;
+        assert_eq!(fs::read(decrypted)?, fs::read("Cargo.toml")?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_associated_data() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let encrypted = dir.path().join("aad.enc");
+        let decrypted = dir.path().join("aad.dec");
+        let (encrypted, decrypted) = (encrypted.to_str().unwrap(), decrypted.to_str().unwrap());
+        let (key, cipher) = (Some(KEY), Cipher::XChaCha20Poly1305);
+        let aad = Some(b"tenant=42".as_slice());
+
+        process_stream_encrypt(key, "Cargo.toml", encrypted, cipher, aad, None).await?;
+        process_text_decrypt(key, &[], encrypted, decrypted, aad, None).await?;
+        assert_eq!(fs::read(decrypted)?, fs::read("Cargo.toml")?);
+        let other = Some(b"tenant=43".as_slice());
+        for aad in [None, other] {
+            let res = process_text_decrypt(key, &[], encrypted, decrypted, aad, None).await;
+            assert!(res.is_err());
+        }
+
+        let armored = process_text_encrypt(key, "Cargo.toml", cipher, aad, None).await?;
+        fs::write(encrypted, armored)?;
+        process_text_decrypt(key, &[], encrypted, decrypted, aad, None).await?;
+        assert!(
+            process_text_decrypt(key, &[], encrypted, decrypted, other, None)
+                .await
+                .is_err()
+        );
+
+        // a message without associated data rejects any
+        let armored = process_text_encrypt(key, "Cargo.toml", cipher, None, None).await?;
+        fs::write(encrypted, armored)?;
+        assert!(
+            process_text_decrypt(key, &[], encrypted, decrypted, aad, None)
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cipher_choices() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let encrypted = dir.path().join("cipher.enc");
+        let decrypted = dir.path().join("cipher.dec");
+        let (encrypted, decrypted) = (encrypted.to_str().unwrap(), decrypted.to_str().unwrap());
+        let expected = fs::read("Cargo.toml")?;
+        for cipher in [
+            Cipher::ChaCha20Poly1305,
+            Cipher::XChaCha20Poly1305,
+            Cipher::Aes256Gcm,
+            Cipher::Aes256GcmSiv,
+        ] {
+            process_stream_encrypt(Some(KEY), "Cargo.toml", encrypted, cipher, None, None).await?;
+            let envelope = Envelope::read_from(&mut fs::File::open(encrypted)?)?;
+            assert_eq!(envelope.cipher, cipher);
+            process_text_decrypt(Some(KEY), &[], encrypted, decrypted, None, None).await?;
+            assert_eq!(fs::read(decrypted)?, expected);
+
+            let armored = process_text_encrypt(Some(KEY), "Cargo.toml", cipher, None, None).await?;
+            fs::write(encrypted, armored)?;
+            process_text_decrypt(Some(KEY), &[], encrypted, decrypted, None, None).await?;
+            assert_eq!(fs::read(decrypted)?, expected);
+        }
+        Ok(())
+    }
 }
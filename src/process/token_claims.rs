@@ -0,0 +1,301 @@
+use anyhow::Result;
+use rand::{rngs::OsRng, RngCore};
+use serde_json::{Map, Value};
+use std::{fs, io::Read};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// Claims of a new token. The registered claims set here override the same
+/// keys in `custom`; `iat` defaults to now and `jti` to a random id.
+#[derive(Debug, Default)]
+pub struct Claims {
+    pub sub: Option<String>,
+    pub aud: Option<String>,
+    pub iss: Option<String>,
+    pub exp: Option<u64>,
+    pub nbf: Option<u64>,
+    pub iat: Option<u64>,
+    pub jti: Option<String>,
+    pub custom: Map<String, Value>,
+}
+
+impl Claims {
+    pub(crate) fn into_map(self) -> Map<String, Value> {
+        let mut claims = self.custom;
+        let strings = [
+            ("sub", self.sub),
+            ("aud", self.aud),
+            ("iss", self.iss),
+            ("jti", Some(self.jti.unwrap_or_else(generate_jti))),
+        ];
+        for (name, value) in strings {
+            if let Some(value) = value {
+                claims.insert(name.into(), value.into());
+            }
+        }
+        let iat = self.iat.unwrap_or_else(jsonwebtoken::get_current_timestamp);
+        let times = [("exp", self.exp), ("nbf", self.nbf), ("iat", Some(iat))];
+        for (name, value) in times {
+            if let Some(value) = value {
+                claims.insert(name.into(), value.into());
+            }
+        }
+        claims
+    }
+}
+
+fn generate_jti() -> String {
+    let mut id = [0u8; 16];
+    OsRng.fill_bytes(&mut id);
+    hex::encode(id)
+}
+
+/// Read custom claims from a file holding a JSON object.
+pub fn read_claims_file(path: &str) -> Result<Map<String, Value>> {
+    match serde_json::from_slice(&fs::read(path)?)? {
+        Value::Object(claims) => Ok(claims),
+        _ => Err(anyhow::anyhow!("{} must hold a JSON object", path)),
+    }
+}
+
+/// Read a compact token, ignoring surrounding whitespace.
+pub fn read_token(reader: &mut dyn Read) -> Result<String> {
+    let mut token = String::new();
+    reader.read_to_string(&mut token)?;
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(anyhow::anyhow!("No token given"));
+    }
+    Ok(token.to_string())
+}
+
+/// Where a token stands against its `exp` and `nbf` claims.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeStatus {
+    Valid,
+    Expired,
+    NotYetValid,
+}
+
+/// Format a Unix timestamp as RFC 3339 in UTC.
+pub fn format_timestamp(timestamp: i64) -> Result<String> {
+    Ok(OffsetDateTime::from_unix_timestamp(timestamp)?.format(&Rfc3339)?)
+}
+
+/// Describe `timestamp` relative to `now` with its two largest units, such
+/// as `in 2h 5m` or `3d 4h ago`.
+pub fn format_relative(timestamp: i64, now: i64) -> String {
+    const UNITS: [(&str, u64); 4] = [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)];
+    let mut rest = timestamp.abs_diff(now);
+    if rest == 0 {
+        return "now".into();
+    }
+    let parts: Vec<_> = UNITS
+        .iter()
+        .filter_map(|(unit, secs)| {
+            let n = rest / secs;
+            rest %= secs;
+            (n > 0).then(|| format!("{}{}", n, unit))
+        })
+        .take(2)
+        .collect();
+    if timestamp > now {
+        format!("in {}", parts.join(" "))
+    } else {
+        format!("{} ago", parts.join(" "))
+    }
+}
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+const MONTH: u64 = 30 * DAY;
+const YEAR: u64 = 365 * DAY;
+
+/// Split the leading run of ASCII digits off `s` and parse it.
+fn split_number<'a>(s: &'a str, input: &str) -> Result<(u64, &'a str)> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    if end == 0 {
+        return Err(anyhow::anyhow!(
+            "Expected a number at '{}' in duration '{}'",
+            s,
+            input
+        ));
+    }
+    let num = s[..end]
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Number too large in duration '{}'", input))?;
+    Ok((num, &s[end..]))
+}
+
+fn add_duration(total: u64, num: u64, unit: u64, input: &str) -> Result<u64> {
+    num.checked_mul(unit)
+        .and_then(|secs| total.checked_add(secs))
+        .ok_or_else(|| anyhow::anyhow!("Duration '{}' is too large", input))
+}
+
+/// Parse an ISO 8601 duration such as `P1DT2H` or `PT90M`, `body` being the
+/// part after the leading `P`.
+fn parse_iso_duration(body: &str, input: &str) -> Result<u64> {
+    const DATE_UNITS: [(char, u64); 4] = [('Y', YEAR), ('M', MONTH), ('W', WEEK), ('D', DAY)];
+    const TIME_UNITS: [(char, u64); 3] = [('H', HOUR), ('M', MINUTE), ('S', 1)];
+    let (date, time) = match body.split_once(['T', 't']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (body, None),
+    };
+    if body.is_empty() || time == Some("") {
+        return Err(anyhow::anyhow!("Empty ISO 8601 duration '{}'", input));
+    }
+    let mut total = 0;
+    for (mut rest, units) in [
+        (date, &DATE_UNITS[..]),
+        (time.unwrap_or_default(), &TIME_UNITS[..]),
+    ] {
+        // designators must appear at most once and in order
+        let mut next = 0;
+        while !rest.is_empty() {
+            let (num, tail) = split_number(rest, input)?;
+            let mut chars = tail.chars();
+            let designator = chars.next().map(|c| c.to_ascii_uppercase());
+            let index = units[next..]
+                .iter()
+                .position(|(c, _)| Some(*c) == designator)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Invalid ISO 8601 duration '{}' at '{}'", input, tail)
+                })?;
+            total = add_duration(total, num, units[next + index].1, input)?;
+            next += index + 1;
+            rest = chars.as_str();
+        }
+    }
+    Ok(total)
+}
+
+/// Parse a duration in seconds: either units run together, such as `90s`,
+/// `1h30m`, `2w` or `3mo`, or an ISO 8601 duration such as `P1DT12H`. Units
+/// are case-insensitive; a month is 30 days and a year 365.
+pub fn parse_duration(input: &str) -> Result<u64> {
+    let s = input.trim();
+    if let Some(body) = s.strip_prefix(['P', 'p']) {
+        return parse_iso_duration(body, input);
+    }
+    if s.is_empty() {
+        return Err(anyhow::anyhow!("Empty duration"));
+    }
+    let mut total = 0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let (num, tail) = split_number(rest, input)?;
+        let end = tail
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(tail.len());
+        let unit = match tail[..end].to_ascii_lowercase().as_str() {
+            "s" => 1,
+            "m" => MINUTE,
+            "h" => HOUR,
+            "d" => DAY,
+            "w" => WEEK,
+            "mo" => MONTH,
+            "y" => YEAR,
+            "" => {
+                return Err(anyhow::anyhow!(
+                    "Missing unit after {} in duration '{}'",
+                    num,
+                    input
+                ))
+            }
+            unit => {
+                return Err(anyhow::anyhow!(
+                    "Unknown unit '{}' in duration '{}', expected s, m, h, d, w, mo or y",
+                    unit,
+                    input
+                ))
+            }
+        };
+        total = add_duration(total, num, unit, input)?;
+        rest = tail[end..].trim_start();
+    }
+    Ok(total)
+}
+
+/// Resolve a time given as an RFC 3339 timestamp, or as a duration from `now`
+/// that is in the past when prefixed with `-`. Returns a Unix timestamp.
+pub fn parse_time(input: &str, now: u64) -> Result<u64> {
+    let s = input.trim();
+    // a date starts like `2024-`, which no duration does
+    if s.get(4..5) == Some("-") {
+        let timestamp = OffsetDateTime::parse(s, &Rfc3339)
+            .map_err(|e| anyhow::anyhow!("Invalid RFC 3339 timestamp '{}': {}", input, e))?
+            .unix_timestamp();
+        return u64::try_from(timestamp)
+            .map_err(|_| anyhow::anyhow!("Timestamp '{}' is before 1970", input));
+    }
+    match s.strip_prefix('-') {
+        Some(ago) => now
+            .checked_sub(parse_duration(ago)?)
+            .ok_or_else(|| anyhow::anyhow!("Time '{}' is before 1970", input)),
+        None => now
+            .checked_add(parse_duration(s)?)
+            .ok_or_else(|| anyhow::anyhow!("Duration '{}' is too large", input)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_times() -> Result<()> {
+        assert_eq!(format_timestamp(0)?, "1970-01-01T00:00:00Z");
+        assert_eq!(format_relative(100 + 7500, 100), "in 2h 5m");
+        assert_eq!(
+            format_relative(100, 100 + 3 * 86400 + 4 * 3600 + 1),
+            "3d 4h ago"
+        );
+        assert_eq!(format_relative(100, 100), "now");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_duration() -> Result<()> {
+        assert_eq!(parse_duration("90s")?, 90);
+        assert_eq!(parse_duration("1h30m")?, 5400);
+        assert_eq!(parse_duration("1H 30M")?, 5400);
+        assert_eq!(parse_duration("2w")?, 14 * DAY);
+        assert_eq!(parse_duration("1mo1d")?, 31 * DAY);
+        assert_eq!(parse_duration("1y")?, 365 * DAY);
+        assert_eq!(parse_duration("P1DT2H")?, DAY + 2 * HOUR);
+        assert_eq!(parse_duration("PT90M")?, 5400);
+        assert_eq!(parse_duration("P1M")?, MONTH);
+        assert_eq!(parse_duration("P2W")?, 2 * WEEK);
+        for bad in [
+            "",
+            "10",
+            "1x",
+            "h",
+            "1h-",
+            "P",
+            "P1DT",
+            "PT1D",
+            "P1D1Y",
+            "P1H",
+            "99999999999999y",
+        ] {
+            assert!(parse_duration(bad).is_err(), "{}", bad);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_time() -> Result<()> {
+        let now = 1_000_000;
+        assert_eq!(parse_time("1h", now)?, now + 3600);
+        assert_eq!(parse_time("-1d", now)?, now - DAY);
+        assert_eq!(parse_time("2024-01-01T00:00:00Z", now)?, 1_704_067_200);
+        assert_eq!(parse_time("2024-01-01T02:00:00+02:00", now)?, 1_704_067_200);
+        assert!(parse_time("2024-01-01", now).is_err());
+        assert!(parse_time("2024-13-01T00:00:00Z", now).is_err());
+        assert!(parse_time("-1y", 0).is_err());
+        Ok(())
+    }
+}
@@ -1,9 +1,27 @@
 use anyhow::Result;
 use std::{
-    fs::File,
+    env, fmt,
+    fs::{self, File},
     io::{self, Read, Write},
     path::Path,
 };
+use zeroize::Zeroizing;
+
+/// Environment variable holding the passphrase for protected keys.
+pub const PASSPHRASE_ENV: &str = "RCLI_PASSPHRASE";
+
+/// Returned by the verify commands when the check ran but did not pass, so
+/// that `main` can exit with 1 (invalid) rather than 2 (error).
+#[derive(Debug)]
+pub struct VerificationFailed;
+
+impl fmt::Display for VerificationFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Verification failed")
+    }
+}
+
+impl std::error::Error for VerificationFailed {}
 
 pub fn parse_file(input: &str) -> Result<String, String> {
     if Path::new(input).exists() || input == "-" {
@@ -41,3 +59,24 @@ pub fn get_writer(output: &str) -> Result<Box<dyn Write>> {
     };
     Ok(writer)
 }
+
+/// Get a passphrase from `file`, the `RCLI_PASSPHRASE` environment variable or
+/// an interactive prompt, in that order. Only the prompt asks for confirmation.
+pub fn read_passphrase(file: Option<&str>, confirm: bool) -> Result<Zeroizing<String>> {
+    let passphrase = if let Some(file) = file {
+        let content = Zeroizing::new(fs::read_to_string(file)?);
+        Zeroizing::new(content.trim_end_matches(['\r', '\n']).to_string())
+    } else if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        Zeroizing::new(passphrase)
+    } else {
+        let passphrase = Zeroizing::new(rpassword::prompt_password("Enter passphrase: ")?);
+        if confirm && *rpassword::prompt_password("Confirm passphrase: ")? != *passphrase {
+            return Err(anyhow::anyhow!("Passphrases do not match"));
+        }
+        passphrase
+    };
+    if passphrase.is_empty() {
+        return Err(anyhow::anyhow!("Passphrase must not be empty"));
+    }
+    Ok(passphrase)
+}